@@ -2,4 +2,49 @@
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
-}
\ No newline at end of file
+    if std::env::var_os("CARGO_FEATURE_EMBEDDED_STATIC_FILES").is_some() {
+        embed_static_files();
+    }
+}
+
+const STATIC_FILES_DIRECTORY: &str = "static_files";
+
+fn collect_files(directory: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+    let entries = std::fs::read_dir(directory).expect("Failed to read the static files directory");
+    for entry in entries {
+        let path = entry.expect("Failed to read the static files directory").path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        match path.is_dir() {
+            true => collect_files(&path, files),
+            false => files.push(path),
+        }
+    }
+}
+
+// Generates a slice of (path, content) pairs sorted by path, paths are relative to the static files directory
+fn embed_static_files() {
+    println!("cargo:rerun-if-changed={}", STATIC_FILES_DIRECTORY);
+    let directory = std::path::Path::new(&std::env::var("CARGO_MANIFEST_DIR").expect("Cargo sets CARGO_MANIFEST_DIR"))
+        .join(STATIC_FILES_DIRECTORY);
+    let mut files = Vec::new();
+    collect_files(&directory, &mut files);
+    let mut entries = files.iter()
+        .map(|path| {
+            let name = path.strip_prefix(&directory).expect("Files are collected from the static files directory")
+                .components()
+                .map(|v| v.as_os_str().to_str().expect("Static file paths have to be valid UTF-8"))
+                .collect::<Vec<_>>()
+                .join("/");
+            (name, path)
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    let mut code = String::from("pub(super) static EMBEDDED_STATIC_FILES: &[(&str, &[u8])] = &[\n");
+    for (name, path) in entries {
+        code.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", name, path));
+    }
+    code.push_str("];\n");
+    let out_path = std::path::Path::new(&std::env::var("OUT_DIR").expect("Cargo sets OUT_DIR"))
+        .join("embedded_static_files.rs");
+    std::fs::write(out_path, code).expect("Failed to write the embedded static files");
+}
@@ -0,0 +1 @@
+ALTER TABLE BlogPosts ADD COLUMN updated_at TIMESTAMP NULL DEFAULT NULL;
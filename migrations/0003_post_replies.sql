@@ -0,0 +1,3 @@
+ALTER TABLE BlogPosts ADD COLUMN parent_id INTEGER NULL DEFAULT NULL REFERENCES BlogPosts(id);
+
+CREATE INDEX BlogPostsParentIdIndex ON BlogPosts(parent_id);
@@ -0,0 +1,21 @@
+CREATE VIRTUAL TABLE BlogPostsSearch USING fts5(
+    user_name,
+    content,
+    content = 'BlogPosts',
+    content_rowid = 'id'
+);
+
+INSERT INTO BlogPostsSearch(BlogPostsSearch) VALUES ('rebuild');
+
+CREATE TRIGGER BlogPostsSearchInsert AFTER INSERT ON BlogPosts BEGIN
+    INSERT INTO BlogPostsSearch(rowid, user_name, content) VALUES (new.id, new.user_name, new.content);
+END;
+
+CREATE TRIGGER BlogPostsSearchDelete AFTER DELETE ON BlogPosts BEGIN
+    INSERT INTO BlogPostsSearch(BlogPostsSearch, rowid, user_name, content) VALUES ('delete', old.id, old.user_name, old.content);
+END;
+
+CREATE TRIGGER BlogPostsSearchUpdate AFTER UPDATE OF user_name, content ON BlogPosts BEGIN
+    INSERT INTO BlogPostsSearch(BlogPostsSearch, rowid, user_name, content) VALUES ('delete', old.id, old.user_name, old.content);
+    INSERT INTO BlogPostsSearch(rowid, user_name, content) VALUES (new.id, new.user_name, new.content);
+END;
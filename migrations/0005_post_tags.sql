@@ -0,0 +1,16 @@
+CREATE TABLE Tags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE PostTags (
+    post_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    -- Explicit tags were given with the post, the others were extracted from hashtags in its content
+    is_explicit BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY(post_id, tag_id),
+    FOREIGN KEY(post_id) REFERENCES BlogPosts(id) ON DELETE CASCADE,
+    FOREIGN KEY(tag_id) REFERENCES Tags(id)
+);
+
+CREATE INDEX PostTagsTagIdIndex ON PostTags(tag_id);
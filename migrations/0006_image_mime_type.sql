@@ -0,0 +1,4 @@
+-- Only PNG images were accepted before
+ALTER TABLE Images ADD COLUMN mime_type TEXT NOT NULL DEFAULT 'image/png';
+
+CREATE INDEX ImagesFilenameIndex ON Images(image_filename);
@@ -0,0 +1,2 @@
+ALTER TABLE Images ADD COLUMN width INTEGER NULL DEFAULT NULL;
+ALTER TABLE Images ADD COLUMN height INTEGER NULL DEFAULT NULL;
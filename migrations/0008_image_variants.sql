@@ -0,0 +1,10 @@
+CREATE TABLE ImageVariants (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    image_id INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    variant_filename TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    UNIQUE(image_id, width),
+    FOREIGN KEY(image_id) REFERENCES Images(id) ON DELETE CASCADE
+);
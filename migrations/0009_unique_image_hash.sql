@@ -0,0 +1,18 @@
+-- Hashes have always been bound as raw bytes so they are already stored as blobs despite the declared type
+ALTER TABLE Images RENAME COLUMN image_hash TO image_hash_text;
+ALTER TABLE Images ADD COLUMN image_hash BLOB NOT NULL DEFAULT x'';
+UPDATE Images SET image_hash = CAST(image_hash_text AS BLOB);
+ALTER TABLE Images DROP COLUMN image_hash_text;
+
+-- Concurrent uploads could have created duplicates, posts are moved to the oldest row of each hash.
+-- Files of the removed rows are left to the image garbage collector
+UPDATE BlogPosts
+SET user_avatar = (SELECT MIN(other.id) FROM Images AS current JOIN Images AS other ON other.image_hash = current.image_hash WHERE current.id = BlogPosts.user_avatar)
+WHERE user_avatar IS NOT NULL;
+UPDATE BlogPosts
+SET post_image = (SELECT MIN(other.id) FROM Images AS current JOIN Images AS other ON other.image_hash = current.image_hash WHERE current.id = BlogPosts.post_image)
+WHERE post_image IS NOT NULL;
+DELETE FROM Images
+WHERE id NOT IN (SELECT MIN(id) FROM Images GROUP BY image_hash);
+
+CREATE UNIQUE INDEX ImagesHashIndex ON Images(image_hash);
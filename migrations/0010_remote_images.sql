@@ -0,0 +1,10 @@
+CREATE TABLE RemoteImages (
+    source_url TEXT PRIMARY KEY,
+    image_id INTEGER NOT NULL,
+    fetched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    etag TEXT,
+    last_modified TEXT,
+    FOREIGN KEY(image_id) REFERENCES Images(id) ON DELETE CASCADE
+);
+
+CREATE INDEX RemoteImagesImageIndex ON RemoteImages(image_id);
@@ -0,0 +1,16 @@
+ALTER TABLE BlogPosts ADD COLUMN user_avatar_status TEXT NULL DEFAULT NULL;
+
+CREATE TABLE AvatarJobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER NOT NULL,
+    source_url TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(post_id) REFERENCES BlogPosts(id) ON DELETE CASCADE
+);
+
+CREATE INDEX AvatarJobsPostIndex ON AvatarJobs(post_id);
+CREATE INDEX AvatarJobsStatusIndex ON AvatarJobs(status, next_attempt_at);
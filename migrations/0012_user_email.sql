@@ -0,0 +1 @@
+ALTER TABLE BlogPosts ADD COLUMN user_email_hash TEXT NULL DEFAULT NULL;
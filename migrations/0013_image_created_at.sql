@@ -0,0 +1,4 @@
+-- Columns added to an existing table cannot default to the current time, new rows set it explicitly.
+-- Existing images count as new so the garbage collector leaves them alone for one grace period
+ALTER TABLE Images ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE Images SET created_at = CURRENT_TIMESTAMP;
@@ -0,0 +1 @@
+ALTER TABLE AvatarJobs ADD COLUMN claimed_at TIMESTAMP NULL DEFAULT NULL;
@@ -0,0 +1 @@
+ALTER TABLE AvatarJobs ADD COLUMN is_fallback BOOLEAN NOT NULL DEFAULT FALSE;
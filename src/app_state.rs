@@ -1,5 +1,5 @@
-use std::sync::Arc;
-use crate::{db::DatabasePool, env_variables, services::{blog_post_service::BlogPostService, file_handler_service::FileHandlerService, static_files_service::StaticFilesService}};
+use std::{sync::Arc, time::Duration};
+use crate::{db::DatabasePool, env_variables, services::{avatar_fetcher::{AvatarFetcher, PublicAddressResolver}, avatar_job_queue::AvatarJobQueue, blog_post_service::BlogPostService, file_handler_service::{FileHandlerService, ImageDimensionsLimits, ImageProcessingOptions}, image_garbage_collector::ImageGarbageCollector, static_files_service::StaticFilesService, storage::{filesystem_storage::FilesystemStorage, object_storage::ObjectStorage, StorageBackend}}};
 
 pub(crate) type AppStateType = Arc<AppState>;
 
@@ -11,12 +11,23 @@ pub(crate) enum AppStateInitializationError {
     InvalidPathError,
     #[error("Invalid number")]
     NotValidNumber,
+    #[error("Failed to sweep the staging directory: {0}")]
+    StagingSweepFailed(tokio::io::Error),
+    #[error("Unknown storage backend {0}, supported backends are: filesystem, s3, memory")]
+    UnknownStorageBackend(String),
+    #[error("Invalid S3 configuration: {0}")]
+    InvalidS3Configuration(object_store::Error),
+    #[error("Failed to initialize HTTP client: {0}")]
+    HttpClientInitializationFailed(reqwest::Error),
 }
 
 pub(crate) struct AppState {
     pub blog_post_service: BlogPostService,
     pub file_handler_service: FileHandlerService,
     pub static_files_service: StaticFilesService,
+    pub image_garbage_collector: ImageGarbageCollector,
+    pub http_client: reqwest::Client,
+    pub avatar_job_queue: AvatarJobQueue,
 }
 
 impl AppState {
@@ -25,30 +36,153 @@ impl AppState {
         blog_post_service: BlogPostService,
         file_handler_service: FileHandlerService,
         static_files_service: StaticFilesService,
+        image_garbage_collector: ImageGarbageCollector,
+        http_client: reqwest::Client,
+        avatar_job_queue: AvatarJobQueue,
     ) -> Self {
         Self {
             blog_post_service,
             file_handler_service,
             static_files_service,
+            image_garbage_collector,
+            http_client,
+            avatar_job_queue,
         }
     }
 
+    /// Client for requests to user supplied urls, it does not follow redirects on its own
+    /// and never connects to non-public addresses
+    fn initialize_http_client() -> Result<reqwest::Client, AppStateInitializationError> {
+        use env_variables::get_env_var as var;
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(var(env_variables::HTTP_CONNECT_TIMEOUT)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?))
+            .read_timeout(Duration::from_secs(var(env_variables::HTTP_READ_TIMEOUT)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?))
+            // Covers reading the whole body as well, a remote sending data slowly cannot hold a request indefinitely
+            .timeout(Duration::from_secs(var(env_variables::HTTP_TIMEOUT)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?))
+            .user_agent(var(env_variables::HTTP_USER_AGENT)?)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicAddressResolver))
+            // A proxy would resolve hosts on its own
+            .no_proxy()
+            .build()
+            .map_err(AppStateInitializationError::HttpClientInitializationFailed)
+    }
+
+    /// S3 variables are only required when the S3 backend is selected
+    fn initialize_storage() -> Result<Arc<dyn StorageBackend>, AppStateInitializationError> {
+        use env_variables::get_env_var as var;
+        Ok(match var(env_variables::STORAGE_BACKEND)?.as_str() {
+            "filesystem" => Arc::new(FilesystemStorage::new(var(env_variables::UPLOAD_DIRECTORY)?.as_str())
+                .ok_or(AppStateInitializationError::InvalidPathError)?),
+            "s3" => Arc::new(ObjectStorage::new(object_store::aws::AmazonS3Builder::new()
+                .with_bucket_name(var(env_variables::S3_BUCKET)?)
+                .with_region(var(env_variables::S3_REGION)?)
+                .with_endpoint(var(env_variables::S3_ENDPOINT)?)
+                .with_access_key_id(var(env_variables::S3_ACCESS_KEY_ID)?)
+                .with_secret_access_key(var(env_variables::S3_SECRET_ACCESS_KEY)?)
+                // Self-hosted S3 compatible services are often reachable only over plain HTTP
+                .with_allow_http(true)
+                .build()
+                .map_err(AppStateInitializationError::InvalidS3Configuration)?)),
+            "memory" => Arc::new(ObjectStorage::new(object_store::memory::InMemory::new())),
+            backend => return Err(AppStateInitializationError::UnknownStorageBackend(backend.to_string())),
+        })
+    }
+
     #[inline]
     pub(crate) async fn initialize(connection_pool: DatabasePool) -> Result<Arc<Self>, AppStateInitializationError> {
         use env_variables::get_env_var as var;
-        let ans = Arc::new(Self::new(
-            BlogPostService::new(connection_pool.clone()),
-            FileHandlerService::new(
-                connection_pool,
-                var(env_variables::UPLOAD_DIRECTORY)?.as_str(),
-                var(env_variables::UPLOAD_BUFFER_SIZE)?
+        let file_handler_service = FileHandlerService::new(
+            connection_pool.clone(),
+            Self::initialize_storage()?,
+            var(env_variables::UPLOAD_DIRECTORY)?.as_str(),
+            var(env_variables::UPLOAD_BUFFER_SIZE)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+            var(env_variables::MAX_BODY_SIZE)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+            ImageDimensionsLimits {
+                max_width: var(env_variables::MAX_IMAGE_WIDTH)?
                     .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
-                var(env_variables::MAX_BODY_SIZE)?
+                max_height: var(env_variables::MAX_IMAGE_HEIGHT)?
                     .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
-            ).ok_or(AppStateInitializationError::InvalidPathError)?,
+                max_pixels: var(env_variables::MAX_IMAGE_PIXELS)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+            },
+            ImageProcessingOptions {
+                variant_widths: var(env_variables::IMAGE_VARIANT_WIDTHS)?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.parse().map_err(|_| AppStateInitializationError::NotValidNumber))
+                    .collect::<Result<_, _>>()?,
+                preserved_metadata: var(env_variables::PRESERVED_IMAGE_METADATA)?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            },
+        ).ok_or(AppStateInitializationError::InvalidPathError)?;
+        file_handler_service.sweep_staging_directory(Duration::from_secs(var(env_variables::STAGING_FILE_MAX_AGE)?
+            .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?))
+            .await.map_err(AppStateInitializationError::StagingSweepFailed)?;
+        let image_garbage_collector = ImageGarbageCollector::new(
+            connection_pool.clone(),
+            file_handler_service.clone(),
+            // Zero disables the scheduled collection
+            Some(Duration::from_secs(var(env_variables::IMAGE_GC_INTERVAL)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?))
+                .filter(|v| !v.is_zero()),
+            Duration::from_secs(var(env_variables::IMAGE_GC_GRACE_PERIOD)?
+                .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?),
+        );
+        // Embedded static files make the directory optional
+        #[cfg(feature = "embedded-static-files")]
+        let static_files_directory = var(env_variables::STATIC_FILES_DIRECTORY).ok();
+        #[cfg(not(feature = "embedded-static-files"))]
+        let static_files_directory = Some(var(env_variables::STATIC_FILES_DIRECTORY)?);
+        let ans = Arc::new(Self::new(
+            BlogPostService::new(
+                connection_pool.clone(),
+                var(env_variables::MAX_REPLY_DEPTH)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+                AvatarFetcher::new(
+                    var(env_variables::AVATAR_ALLOWED_DOMAINS)?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    var(env_variables::HTTP_MAX_REDIRECTS)?
+                        .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+                    var(env_variables::MAX_AVATAR_SIZE)?
+                        .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+                ),
+                Duration::from_secs(var(env_variables::REMOTE_IMAGE_TTL)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?),
+            ),
+            file_handler_service,
             StaticFilesService::new(
-                var(env_variables::STATIC_FILES_DIRECTORY)?.as_str()
+                static_files_directory.as_deref(),
+                var(env_variables::COMPRESSION_MIN_SIZE)?
+                    .parse::<u16>().map_err(|_| AppStateInitializationError::NotValidNumber)?.into(),
             ).ok_or(AppStateInitializationError::InvalidPathError)?,
+            image_garbage_collector,
+            Self::initialize_http_client()?,
+            AvatarJobQueue::new(
+                connection_pool.clone(),
+                var(env_variables::AVATAR_WORKERS)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+                var(env_variables::AVATAR_JOB_MAX_ATTEMPTS)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?,
+                Duration::from_secs(var(env_variables::AVATAR_JOB_RETRY_DELAY)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?),
+                Duration::from_secs(var(env_variables::AVATAR_JOB_TIMEOUT)?
+                    .parse().map_err(|_| AppStateInitializationError::NotValidNumber)?),
+            ),
         ));
         let ptr = Arc::downgrade(&ans);
         ans.blog_post_service.set_app_state(ptr).await;
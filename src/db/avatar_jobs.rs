@@ -0,0 +1,171 @@
+use std::time::Duration;
+use super::{DatabaseConnection, DatabasePool};
+
+/// Status of a post whose avatar is still being fetched
+pub(crate) const USER_AVATAR_PENDING: &str = "pending";
+/// Status of a post whose avatar could not be fetched
+pub(crate) const USER_AVATAR_FAILED: &str = "failed";
+
+/// Avatar to be fetched in the background and attached to a post
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct AvatarJob {
+    id: i64,
+    post_id: i64,
+    source_url: String,
+    attempts: i64,
+    is_fallback: bool,
+}
+
+impl AvatarJob {
+    #[inline]
+    pub(crate) fn get_id(&self) -> i64 {
+        self.id
+    }
+
+    #[inline]
+    pub(crate) fn get_post_id(&self) -> i64 {
+        self.post_id
+    }
+
+    #[inline]
+    pub(crate) fn get_source_url(&self) -> &str {
+        &self.source_url
+    }
+
+    /// Number of attempts including the current one
+    #[inline]
+    pub(crate) fn get_attempts(&self) -> i64 {
+        self.attempts
+    }
+
+    /// Set when the url has been derived for the post (e.g. its Gravatar) instead of being given by the user
+    #[inline]
+    pub(crate) fn is_fallback(&self) -> bool {
+        self.is_fallback
+    }
+}
+
+/// Enqueues the fetch and marks the avatar of the post as pending, jobs enqueued for the post before are dropped
+#[inline]
+pub(crate) async fn insert_avatar_job(
+    connection: &mut DatabaseConnection,
+    post_id: i64,
+    source_url: &str,
+    is_fallback: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM AvatarJobs WHERE post_id = ?")
+        .bind(post_id)
+        .execute(&mut *connection)
+        .await?;
+    sqlx::query("INSERT INTO AvatarJobs (post_id, source_url, is_fallback) VALUES (?, ?, ?)")
+        .bind(post_id)
+        .bind(source_url)
+        .bind(is_fallback)
+        .execute(&mut *connection)
+        .await?;
+    sqlx::query("UPDATE BlogPosts SET user_avatar_status = ? WHERE id = ?")
+        .bind(USER_AVATAR_PENDING)
+        .bind(post_id)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Drops the jobs of the post and clears its avatar status
+#[inline]
+pub(crate) async fn cancel_avatar_jobs(connection: &mut DatabaseConnection, post_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM AvatarJobs WHERE post_id = ?")
+        .bind(post_id)
+        .execute(&mut *connection)
+        .await?;
+    sqlx::query("UPDATE BlogPosts SET user_avatar_status = NULL WHERE id = ?")
+        .bind(post_id)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Takes the oldest due job and counts the attempt, a single statement so no two workers get the same job.
+/// Jobs claimed longer than `stale_after` ago are taken over as their worker is assumed to be gone
+#[inline]
+pub(crate) async fn claim_avatar_job(pool: &DatabasePool, stale_after: Duration) -> Result<Option<AvatarJob>, sqlx::Error> {
+    sqlx::query_as::<_, AvatarJob>(
+        "UPDATE AvatarJobs SET status = 'running', attempts = attempts + 1, claimed_at = CURRENT_TIMESTAMP
+        WHERE id = (
+            SELECT id FROM AvatarJobs
+            WHERE (status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP)
+            OR (status = 'running' AND claimed_at <= datetime(CURRENT_TIMESTAMP, '-' || ? || ' seconds'))
+            ORDER BY next_attempt_at, id LIMIT 1
+        )
+        RETURNING id, post_id, source_url, attempts, is_fallback",
+    )
+        .bind(i64::try_from(stale_after.as_secs()).unwrap_or(i64::MAX))
+        .fetch_optional(pool)
+        .await
+}
+
+/// Returns when the next job becomes due, including jobs which become stale, or `None` if there are no jobs
+#[inline]
+pub(crate) async fn get_next_avatar_job_due_date(
+    pool: &DatabasePool,
+    stale_after: Duration,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT MIN(due_date) FROM (
+            SELECT next_attempt_at AS due_date FROM AvatarJobs WHERE status = 'pending'
+            UNION ALL
+            SELECT datetime(claimed_at, '+' || ? || ' seconds') FROM AvatarJobs WHERE status = 'running'
+        )",
+    )
+        .bind(i64::try_from(stale_after.as_secs()).unwrap_or(i64::MAX))
+        .fetch_one(pool)
+        .await
+}
+
+/// Removes a finished job, returns `false` if it has been cancelled in the meantime
+#[inline]
+pub(crate) async fn delete_avatar_job(connection: &mut DatabaseConnection, id: i64) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query("DELETE FROM AvatarJobs WHERE id = ?")
+        .bind(id)
+        .execute(connection)
+        .await?
+        .rows_affected() > 0)
+}
+
+/// Jobs are identified together with the attempt, so a job taken over by another worker is left alone
+#[inline]
+pub(crate) async fn retry_avatar_job(pool: &DatabasePool, job: &AvatarJob, delay: Duration, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE AvatarJobs SET status = 'pending', next_attempt_at = datetime(CURRENT_TIMESTAMP, '+' || ? || ' seconds'), last_error = ?
+        WHERE id = ? AND attempts = ?",
+    )
+        .bind(i64::try_from(delay.as_secs()).unwrap_or(i64::MAX))
+        .bind(error)
+        .bind(job.id)
+        .bind(job.attempts)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Gives up on the job and marks the avatar of the post as failed, the job is kept for inspection
+#[inline]
+pub(crate) async fn fail_avatar_job(pool: &DatabasePool, job: &AvatarJob, error: &str) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let post_id: Option<i64> = sqlx::query_scalar(
+        "UPDATE AvatarJobs SET status = 'failed', last_error = ? WHERE id = ? AND attempts = ? RETURNING post_id",
+    )
+        .bind(error)
+        .bind(job.id)
+        .bind(job.attempts)
+        .fetch_optional(&mut *transaction)
+        .await?;
+    if let Some(post_id) = post_id {
+        sqlx::query("UPDATE BlogPosts SET user_avatar_status = ? WHERE id = ?")
+            .bind(USER_AVATAR_FAILED)
+            .bind(post_id)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await
+}
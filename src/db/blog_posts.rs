@@ -1,23 +1,26 @@
-use super::DatabasePool;
+use super::{DatabaseConnection, DatabasePool};
 
 #[inline]
 pub(crate) async fn insert_post(
-    pool: &DatabasePool,
+    connection: &mut DatabaseConnection,
     user_name: &str,
     content: &str,
+    user_email_hash: Option<&str>,
     user_avatar: Option<i64>,
-    post_image: Option<i64>
-) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        "INSERT INTO BlogPosts (user_name, content, user_avatar, post_image) VALUES (?, ?, ?, ?) RETURNING *",
+    post_image: Option<i64>,
+    parent_id: Option<i64>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO BlogPosts (user_name, content, user_email_hash, user_avatar, post_image, parent_id) VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
     )
         .bind(user_name)
         .bind(content)
+        .bind(user_email_hash)
         .bind(user_avatar)
         .bind(post_image)
-        .execute(pool)
-        .await?;
-    Ok(())
+        .bind(parent_id)
+        .fetch_one(connection)
+        .await
 }
 
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
@@ -26,21 +29,151 @@ pub(crate) struct Post {
     pub user_name: String,
     pub content: String,
     pub user_avatar: Option<String>,
+    /// `pending` while the avatar is being fetched in the background, `failed` if it could not be fetched
+    pub user_avatar_status: Option<String>,
     pub post_image: Option<String>,
     pub publication_date: chrono::DateTime<chrono::Utc>,
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub parent_id: Option<i64>,
+    pub reply_count: i64,
+    pub tags: sqlx::types::Json<Vec<String>>,
 }
 
-#[inline]
-pub(crate) async fn get_newest_posts(pool: &DatabasePool, limit: i64, offset: i64) -> Result<Vec<Post>, sqlx::Error> {
-    sqlx::query_as::<_, Post>(
-        "SELECT BlogPosts.id, user_name, content, user_avatar_table.image_filename AS user_avatar, post_image_table.image_filename AS post_image, publication_date
+const SELECT_POSTS: &str = "SELECT BlogPosts.id, user_name, content, user_avatar_table.image_filename AS user_avatar, user_avatar_status, post_image_table.image_filename AS post_image, publication_date, updated_at, parent_id,
+        (SELECT COUNT(*) FROM BlogPosts AS replies WHERE replies.parent_id = BlogPosts.id) AS reply_count,
+        (SELECT json_group_array(name) FROM (
+            SELECT Tags.name FROM PostTags JOIN Tags ON Tags.id = PostTags.tag_id WHERE PostTags.post_id = BlogPosts.id ORDER BY Tags.name
+        )) AS tags
         FROM BlogPosts
         LEFT JOIN Images AS user_avatar_table ON BlogPosts.user_avatar = user_avatar_table.id
-        LEFT JOIN Images AS post_image_table ON BlogPosts.post_image = post_image_table.id
+        LEFT JOIN Images AS post_image_table ON BlogPosts.post_image = post_image_table.id";
+
+/// Marks the beginning of a matched term in `SearchResult::snippet`
+pub(crate) const SNIPPET_MATCH_START: char = '\u{2}';
+/// Marks the end of a matched term in `SearchResult::snippet`
+pub(crate) const SNIPPET_MATCH_END: char = '\u{3}';
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub(crate) struct SearchResult {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub post: Post,
+    pub snippet: String,
+}
+
+/// Condition matching posts with the given tag or all posts if the tag is `NULL` (the tag has to be bound twice)
+const HAS_TAG: &str = "(? IS NULL OR EXISTS (
+            SELECT 1 FROM PostTags JOIN Tags ON Tags.id = PostTags.tag_id WHERE PostTags.post_id = BlogPosts.id AND Tags.name = ?
+        ))";
+
+#[derive(Debug, Clone, Copy, sqlx::FromRow)]
+pub(crate) struct PostImages {
+    pub user_avatar: Option<i64>,
+    pub post_image: Option<i64>,
+}
+
+#[inline]
+pub(crate) async fn get_post_images(pool: &DatabasePool, id: i64) -> Result<Option<PostImages>, sqlx::Error> {
+    sqlx::query_as::<_, PostImages>(
+        "SELECT user_avatar, post_image FROM BlogPosts WHERE id = ?",
+    )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn post_exists(pool: &DatabasePool, id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM BlogPosts WHERE id = ?)")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn update_post(
+    connection: &mut DatabaseConnection,
+    id: i64,
+    content: Option<&str>,
+    user_avatar: Option<i64>,
+    post_image: Option<i64>
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE BlogPosts SET content = COALESCE(?, content), user_avatar = ?, post_image = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+    )
+        .bind(content)
+        .bind(user_avatar)
+        .bind(post_image)
+        .bind(id)
+        .execute(connection)
+        .await?
+        .rows_affected() > 0)
+}
+
+/// Attaches the avatar to the post and clears its status, returns the avatar it replaced
+/// or `None` if the post does not exist
+#[inline]
+pub(crate) async fn set_user_avatar(
+    connection: &mut DatabaseConnection,
+    id: i64,
+    user_avatar: Option<i64>,
+) -> Result<Option<Option<i64>>, sqlx::Error> {
+    let old_user_avatar = sqlx::query_scalar("SELECT user_avatar FROM BlogPosts WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *connection)
+        .await?;
+    sqlx::query("UPDATE BlogPosts SET user_avatar = ?, user_avatar_status = NULL WHERE id = ?")
+        .bind(user_avatar)
+        .bind(id)
+        .execute(connection)
+        .await?;
+    Ok(old_user_avatar)
+}
+
+/// Deletes the post together with all of its (transitive) replies
+#[inline]
+pub(crate) async fn delete_post(pool: &DatabasePool, id: i64) -> Result<Vec<PostImages>, sqlx::Error> {
+    sqlx::query_as::<_, PostImages>(
+        "WITH RECURSIVE thread(id) AS (
+            SELECT id FROM BlogPosts WHERE id = ?
+            UNION ALL
+            SELECT BlogPosts.id FROM BlogPosts JOIN thread ON BlogPosts.parent_id = thread.id
+        )
+        DELETE FROM BlogPosts WHERE id IN thread RETURNING user_avatar, post_image",
+    )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+}
+
+/// Returns the depth a reply to the given post would have (`1` for a reply to a top-level post)
+/// or `None` if the post does not exist
+#[inline]
+pub(crate) async fn get_reply_depth(pool: &DatabasePool, parent_id: i64) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "WITH RECURSIVE ancestors(id, parent_id, depth) AS (
+            SELECT id, parent_id, 1 FROM BlogPosts WHERE id = ?
+            UNION ALL
+            SELECT BlogPosts.id, BlogPosts.parent_id, ancestors.depth + 1 FROM BlogPosts JOIN ancestors ON BlogPosts.id = ancestors.parent_id
+        )
+        SELECT MAX(depth) FROM ancestors",
+    )
+        .bind(parent_id)
+        .fetch_one(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_newest_posts(pool: &DatabasePool, tag: Option<&str>, limit: i64, offset: i64) -> Result<Vec<Post>, sqlx::Error> {
+    sqlx::query_as::<_, Post>(
+        &format!("{SELECT_POSTS}
+        WHERE parent_id IS NULL AND {HAS_TAG}
         ORDER BY publication_date DESC
         LIMIT ?
-        OFFSET ?",
+        OFFSET ?"),
     )
+        .bind(tag)
+        .bind(tag)
         .bind(limit)
         .bind(offset)
         .fetch_all(pool)
@@ -48,8 +181,45 @@ pub(crate) async fn get_newest_posts(pool: &DatabasePool, limit: i64, offset: i6
 }
 
 #[inline]
-pub(crate) async fn get_total_amount_of_posts(pool: &DatabasePool) -> Result<i64, sqlx::Error> {
-    sqlx::query_scalar("SELECT COUNT(*) FROM BlogPosts")
+pub(crate) async fn get_post(pool: &DatabasePool, id: i64) -> Result<Option<Post>, sqlx::Error> {
+    sqlx::query_as::<_, Post>(
+        &format!("{SELECT_POSTS}
+        WHERE BlogPosts.id = ?"),
+    )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_replies(pool: &DatabasePool, parent_id: i64, limit: i64, offset: i64) -> Result<Vec<Post>, sqlx::Error> {
+    sqlx::query_as::<_, Post>(
+        &format!("{SELECT_POSTS}
+        WHERE parent_id = ?
+        ORDER BY publication_date ASC, BlogPosts.id ASC
+        LIMIT ?
+        OFFSET ?"),
+    )
+        .bind(parent_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_total_amount_of_replies(pool: &DatabasePool, parent_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM BlogPosts WHERE parent_id = ?")
+        .bind(parent_id)
+        .fetch_one(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_total_amount_of_posts(pool: &DatabasePool, tag: Option<&str>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM BlogPosts WHERE parent_id IS NULL AND {HAS_TAG}"))
+        .bind(tag)
+        .bind(tag)
         .fetch_one(pool)
         .await
 }
@@ -57,12 +227,37 @@ pub(crate) async fn get_total_amount_of_posts(pool: &DatabasePool) -> Result<i64
 #[inline]
 pub(crate) async fn get_all_newest_posts(pool: &DatabasePool) -> Result<Vec<Post>, sqlx::Error> {
     sqlx::query_as::<_, Post>(
-        "SELECT BlogPosts.id, user_name, content, user_avatar_table.image_filename AS user_avatar, post_image_table.image_filename AS post_image, publication_date
-        FROM BlogPosts
-        LEFT JOIN Images AS user_avatar_table ON BlogPosts.user_avatar = user_avatar_table.id
-        LEFT JOIN Images AS post_image_table ON BlogPosts.post_image = post_image_table.id
-        ORDER BY publication_date DESC",
+        &format!("{SELECT_POSTS}
+        WHERE parent_id IS NULL
+        ORDER BY publication_date DESC"),
+    )
+        .fetch_all(pool)
+        .await
+}
+
+/// `query` has to be a valid FTS5 query, results are ordered by relevance
+#[inline]
+pub(crate) async fn search_posts(pool: &DatabasePool, query: &str, limit: i64, offset: i64) -> Result<Vec<SearchResult>, sqlx::Error> {
+    sqlx::query_as::<_, SearchResult>(
+        &format!("SELECT Posts.*, snippet(BlogPostsSearch, 1, char(2), char(3), '...', 16) AS snippet
+        FROM BlogPostsSearch
+        JOIN ({SELECT_POSTS}) AS Posts ON Posts.id = BlogPostsSearch.rowid
+        WHERE BlogPostsSearch MATCH ?
+        ORDER BY rank
+        LIMIT ?
+        OFFSET ?"),
     )
+        .bind(query)
+        .bind(limit)
+        .bind(offset)
         .fetch_all(pool)
         .await
 }
+
+#[inline]
+pub(crate) async fn get_total_amount_of_search_results(pool: &DatabasePool, query: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM BlogPostsSearch WHERE BlogPostsSearch MATCH ?")
+        .bind(query)
+        .fetch_one(pool)
+        .await
+}
@@ -1,9 +1,12 @@
-use super::DatabasePool;
+use super::{DatabaseConnection, DatabasePool};
 
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub(crate) struct Image {
     id: i64,
+    image_hash: Vec<u8>,
     image_filename: String,
+    mime_type: String,
+    created_at: chrono::DateTime<chrono::Utc>,
 }
 
 impl Image {
@@ -15,25 +18,193 @@ impl Image {
     pub(crate) fn get_filename(&self) -> &str {
         self.image_filename.as_str()
     }
+
+    /// SHA-256 of the stored file
+    #[inline]
+    pub(crate) fn get_hash(&self) -> &[u8] {
+        self.image_hash.as_slice()
+    }
+
+    #[inline]
+    pub(crate) fn get_mime_type(&self) -> &str {
+        self.mime_type.as_str()
+    }
+
+    /// When the row has been inserted, its file is stored only after the row has been committed
+    #[inline]
+    pub(crate) fn get_created_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.created_at
+    }
 }
 
+/// Returns the already existing row if an image with the same hash has been inserted before
 #[inline]
-pub(crate) async fn insert_image(pool: &DatabasePool, image_hash: &[u8], image_filename: String) -> Result<Image, sqlx::Error> {
+pub(crate) async fn insert_image(
+    connection: &mut DatabaseConnection,
+    image_hash: &[u8],
+    image_filename: String,
+    mime_type: &str,
+    dimensions: Option<(u32, u32)>,
+) -> Result<Image, sqlx::Error> {
     sqlx::query_as::<_, Image>(
-        "INSERT INTO Images (image_hash, image_filename) VALUES (?, ?) RETURNING *"
+        "INSERT INTO Images (image_hash, image_filename, mime_type, width, height, created_at) VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+        ON CONFLICT (image_hash) DO UPDATE SET image_hash = excluded.image_hash
+        RETURNING id, image_hash, image_filename, mime_type, created_at"
     )
         .bind(image_hash)
         .bind(image_filename)
-        .fetch_one(pool)
+        .bind(mime_type)
+        .bind(dimensions.map(|(width, _)| width))
+        .bind(dimensions.map(|(_, height)| height))
+        .fetch_one(connection)
         .await
 }
 
 #[inline]
 pub(crate) async fn get_image_by_hash(pool: &DatabasePool, image_hash: &[u8]) -> Result<Option<Image>, sqlx::Error> {
     sqlx::query_as::<_, Image>(
-        "SELECT id, image_filename FROM Images WHERE image_hash = ?"
+        "SELECT id, image_hash, image_filename, mime_type, created_at FROM Images WHERE image_hash = ?"
     )
         .bind(image_hash)
         .fetch_optional(pool)
         .await
 }
+
+#[inline]
+pub(crate) async fn get_image_by_filename(pool: &DatabasePool, image_filename: &str) -> Result<Option<Image>, sqlx::Error> {
+    sqlx::query_as::<_, Image>(
+        "SELECT id, image_hash, image_filename, mime_type, created_at FROM Images WHERE image_filename = ?"
+    )
+        .bind(image_filename)
+        .fetch_optional(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn delete_image_if_unreferenced(pool: &DatabasePool, id: i64) -> Result<Option<Image>, sqlx::Error> {
+    sqlx::query_as::<_, Image>(
+        "DELETE FROM Images
+        WHERE id = ?
+        AND NOT EXISTS (SELECT 1 FROM BlogPosts WHERE BlogPosts.user_avatar = Images.id OR BlogPosts.post_image = Images.id)
+        RETURNING id, image_hash, image_filename, mime_type, created_at"
+    )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_all_images(pool: &DatabasePool) -> Result<Vec<Image>, sqlx::Error> {
+    sqlx::query_as::<_, Image>("SELECT id, image_hash, image_filename, mime_type, created_at FROM Images")
+        .fetch_all(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_unreferenced_images(pool: &DatabasePool) -> Result<Vec<Image>, sqlx::Error> {
+    sqlx::query_as::<_, Image>(
+        "SELECT id, image_hash, image_filename, mime_type, created_at FROM Images
+        WHERE NOT EXISTS (SELECT 1 FROM BlogPosts WHERE BlogPosts.user_avatar = Images.id OR BlogPosts.post_image = Images.id)"
+    )
+        .fetch_all(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn is_image_referenced(pool: &DatabasePool, id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM BlogPosts WHERE user_avatar = ? OR post_image = ?)"
+    )
+        .bind(id)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct ImageVariant {
+    variant_filename: String,
+    mime_type: String,
+    width: u32,
+}
+
+impl ImageVariant {
+    #[inline]
+    pub(crate) fn get_width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub(crate) fn get_filename(&self) -> &str {
+        self.variant_filename.as_str()
+    }
+
+    #[inline]
+    pub(crate) fn get_mime_type(&self) -> &str {
+        self.mime_type.as_str()
+    }
+}
+
+#[inline]
+pub(crate) async fn insert_image_variant(
+    pool: &DatabasePool,
+    image_id: i64,
+    (width, height): (u32, u32),
+    variant_filename: &str,
+    mime_type: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO ImageVariants (image_id, width, height, variant_filename, mime_type) VALUES (?, ?, ?, ?, ?)"
+    )
+        .bind(image_id)
+        .bind(width)
+        .bind(height)
+        .bind(variant_filename)
+        .bind(mime_type)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Returns the narrowest variant of the image which is at least `min_width` pixels wide
+#[inline]
+pub(crate) async fn get_image_variant(pool: &DatabasePool, image_filename: &str, min_width: u32) -> Result<Option<ImageVariant>, sqlx::Error> {
+    sqlx::query_as::<_, ImageVariant>(
+        "SELECT variant_filename, ImageVariants.mime_type, ImageVariants.width
+        FROM ImageVariants
+        JOIN Images ON Images.id = ImageVariants.image_id
+        WHERE Images.image_filename = ? AND ImageVariants.width >= ?
+        ORDER BY ImageVariants.width ASC
+        LIMIT 1"
+    )
+        .bind(image_filename)
+        .bind(min_width)
+        .fetch_optional(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_image_variants(pool: &DatabasePool, image_id: i64) -> Result<Vec<ImageVariant>, sqlx::Error> {
+    sqlx::query_as::<_, ImageVariant>(
+        "SELECT variant_filename, mime_type, width FROM ImageVariants WHERE image_id = ?"
+    )
+        .bind(image_id)
+        .fetch_all(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn get_all_image_variants(pool: &DatabasePool) -> Result<Vec<ImageVariant>, sqlx::Error> {
+    sqlx::query_as::<_, ImageVariant>("SELECT variant_filename, mime_type, width FROM ImageVariants")
+        .fetch_all(pool)
+        .await
+}
+
+#[inline]
+pub(crate) async fn delete_image_variant(pool: &DatabasePool, variant_filename: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM ImageVariants WHERE variant_filename = ?")
+        .bind(variant_filename)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
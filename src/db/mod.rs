@@ -1,10 +1,14 @@
 use std::str::FromStr;
 
+pub(crate) mod avatar_jobs;
 pub(crate) mod blog_posts;
 pub(crate) mod image;
+pub(crate) mod remote_images;
+pub(crate) mod tags;
 
 pub(crate) type Database = sqlx::Sqlite;
 pub(crate) type DatabasePool = sqlx::Pool<Database>;
+pub(crate) type DatabaseConnection = <Database as sqlx::Database>::Connection;
 
 pub(crate) async fn initialize_db(database_url: &str) -> Result<DatabasePool, sqlx::error::Error> {
     tracing::debug!("Connecting to the database at {}", database_url);
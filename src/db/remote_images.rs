@@ -0,0 +1,89 @@
+use super::{DatabaseConnection, DatabasePool};
+
+/// Image fetched from a remote url, with the validators of the response it has been fetched from
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct RemoteImage {
+    image_id: i64,
+    fetched_at: chrono::DateTime<chrono::Utc>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl RemoteImage {
+    #[inline]
+    pub(crate) fn get_image_id(&self) -> i64 {
+        self.image_id
+    }
+
+    /// When the image has been fetched or last revalidated
+    #[inline]
+    pub(crate) fn get_fetched_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.fetched_at
+    }
+
+    #[inline]
+    pub(crate) fn get_etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+
+    #[inline]
+    pub(crate) fn get_last_modified(&self) -> Option<&str> {
+        self.last_modified.as_deref()
+    }
+}
+
+#[inline]
+pub(crate) async fn get_remote_image(pool: &DatabasePool, source_url: &str) -> Result<Option<RemoteImage>, sqlx::Error> {
+    sqlx::query_as::<_, RemoteImage>(
+        "SELECT image_id, fetched_at, etag, last_modified FROM RemoteImages WHERE source_url = ?"
+    )
+        .bind(source_url)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Points the url at the image, replacing whatever has been fetched from it before
+#[inline]
+pub(crate) async fn upsert_remote_image(
+    connection: &mut DatabaseConnection,
+    source_url: &str,
+    image_id: i64,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO RemoteImages (source_url, image_id, fetched_at, etag, last_modified) VALUES (?, ?, CURRENT_TIMESTAMP, ?, ?)
+        ON CONFLICT (source_url) DO UPDATE SET
+            image_id = excluded.image_id,
+            fetched_at = excluded.fetched_at,
+            etag = excluded.etag,
+            last_modified = excluded.last_modified"
+    )
+        .bind(source_url)
+        .bind(image_id)
+        .bind(etag)
+        .bind(last_modified)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Marks the image as fresh after the remote confirmed it has not changed, validators are only replaced by new ones
+#[inline]
+pub(crate) async fn refresh_remote_image(
+    pool: &DatabasePool,
+    source_url: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE RemoteImages SET fetched_at = CURRENT_TIMESTAMP, etag = COALESCE(?, etag), last_modified = COALESCE(?, last_modified)
+        WHERE source_url = ?"
+    )
+        .bind(etag)
+        .bind(last_modified)
+        .bind(source_url)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use super::{DatabaseConnection, DatabasePool};
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub(crate) struct TagCount {
+    pub name: String,
+    pub post_count: i64,
+}
+
+/// Attaches tags to the post, tags which are already attached are left untouched
+#[inline]
+pub(crate) async fn insert_post_tags(
+    connection: &mut DatabaseConnection,
+    post_id: i64,
+    tags: &[String],
+    is_explicit: bool,
+) -> Result<(), sqlx::Error> {
+    for tag in tags {
+        sqlx::query("INSERT INTO Tags (name) VALUES (?) ON CONFLICT(name) DO NOTHING")
+            .bind(tag)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query(
+            "INSERT INTO PostTags (post_id, tag_id, is_explicit)
+            SELECT ?, id, ? FROM Tags WHERE name = ?
+            ON CONFLICT(post_id, tag_id) DO NOTHING",
+        )
+            .bind(post_id)
+            .bind(is_explicit)
+            .bind(tag)
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Detaches all tags which were extracted from hashtags in the post's content
+#[inline]
+pub(crate) async fn delete_extracted_post_tags(connection: &mut DatabaseConnection, post_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM PostTags WHERE post_id = ? AND is_explicit = FALSE")
+        .bind(post_id)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+#[inline]
+pub(crate) async fn get_tags_with_counts(pool: &DatabasePool) -> Result<Vec<TagCount>, sqlx::Error> {
+    sqlx::query_as::<_, TagCount>(
+        "SELECT Tags.name, COUNT(*) AS post_count
+        FROM Tags
+        JOIN PostTags ON PostTags.tag_id = Tags.id
+        GROUP BY Tags.id
+        ORDER BY post_count DESC, Tags.name ASC",
+    )
+        .fetch_all(pool)
+        .await
+}
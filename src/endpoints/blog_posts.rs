@@ -1,15 +1,21 @@
 
-use axum::{extract::{multipart::Field, DefaultBodyLimit, Multipart, Query, State}, http::StatusCode, response::{IntoResponse, Redirect, Response}, routing::{get, post}, Json, Router};
-use crate::app_state::AppStateType;
-use super::{models::get_posts_response::GetPostsResponse, RouterType};
+use axum::{extract::{multipart::Field, DefaultBodyLimit, Multipart, Path, Query, State}, http::StatusCode, response::{IntoResponse, Redirect, Response}, routing::{get, post}, Json, Router};
+use tower_http::compression::{predicate::SizeAbove, CompressionLayer};
+use crate::{app_state::AppStateType, services::blog_post_service::{AddingBlogPostError, ImageChange, PostAuthor}};
+use super::{models::{get_posts_response::GetPostsResponse, search_posts_response::SearchPostsResponse}, RouterType};
 
 #[inline]
-pub(super) fn initialize(max_body_size: usize) -> RouterType {
+pub(super) fn initialize(max_body_size: usize, compression_min_size: u16) -> RouterType {
+    let compression = CompressionLayer::new()
+        .compress_when(SizeAbove::new(compression_min_size));
     Router::new()
         .route("/add", post(add_post))
+        .route("/:id", get(get_post).put(replace_post).patch(edit_post).delete(delete_post))
+        .route("/:id/replies", get(get_replies).post(add_reply))
         .layer(DefaultBodyLimit::max(max_body_size))
-        .route("/get", get(get_posts))
-        .route("/get_all", get(get_posts_all))
+        .route("/get", get(get_posts).layer(compression.clone()))
+        .route("/get_all", get(get_posts_all).layer(compression))
+        .route("/search", get(search_posts))
 }
 
 async fn get_field_text(field: Field<'_>) -> Result<Option<String>, StatusCode> {
@@ -37,19 +43,47 @@ fn create_redirection_with_params(destination: &str, params: &[(&str, &str)]) ->
     Ok(Redirect::to(&destination).into_response())
 }
 
+#[inline]
 async fn add_post(
     State(app_state): State<AppStateType>,
-    mut req: Multipart
+    req: Multipart
+) -> Result<Response, StatusCode> {
+    create_post(app_state, req, None, "/home").await
+}
+
+#[inline]
+async fn add_reply(
+    State(app_state): State<AppStateType>,
+    Path(parent_id): Path<i64>,
+    req: Multipart
+) -> Result<Response, StatusCode> {
+    create_post(app_state, req, Some(parent_id), &format!("/home/post/{}", parent_id)).await
+}
+
+// Handles a form submission of a new post (or a reply when `parent_id` is set) redirecting to `destination`
+async fn create_post(
+    app_state: AppStateType,
+    mut req: Multipart,
+    parent_id: Option<i64>,
+    destination: &str,
 ) -> Result<Response, StatusCode> {
     let mut user_name = None;
     let mut content = None;
     let mut user_avatar_url = None;
+    let mut user_email = None;
     let mut post_image = None;
+    let mut tags = Vec::new();
     while let Ok(Some(field)) = req.next_field().await {
         match field.name() {
             Some("user_name") => user_name = get_field_text(field).await?,
+            Some("tags") => if let Some(text) = get_field_text(field).await? {
+                tags.extend(text.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| tag.to_string()));
+            },
             Some("content") => content = get_field_text(field).await?,
             Some("user_avatar_url") => user_avatar_url = get_field_text(field).await?,
+            Some("user_email") => user_email = get_field_text(field).await?,
             Some("post_image") => {
                 if let Some("") = field.file_name() {
                     continue;
@@ -60,14 +94,19 @@ async fn add_post(
                     Err(err) => {
                         use crate::services::file_handler_service::FileHandlerServiceError;
                         return match err {
-                            FileHandlerServiceError::FileIsNotAnPNGImage => 
-                                create_redirection_with_params("/home", &[("error", "File is not an PNG image")]),
-                            FileHandlerServiceError::SqlxError(_) | FileHandlerServiceError::TokioIoError(_) => {
+                            FileHandlerServiceError::UnsupportedFileFormat
+                            | FileHandlerServiceError::InvalidPngImage(_)
+                            | FileHandlerServiceError::InvalidImageHeader(_)
+                            | FileHandlerServiceError::ImageDimensionsTooBig { .. } =>
+                                create_redirection_with_params(destination, &[("error", &err.to_string())]),
+                            FileHandlerServiceError::SqlxError(_)
+                            | FileHandlerServiceError::TokioIoError(_)
+                            | FileHandlerServiceError::StorageFailed(_) => {
                                 tracing::error!("Error saving image: {:?}", err);
-                                create_redirection_with_params("/home", &[("error", "Internal server error")])
+                                create_redirection_with_params(destination, &[("error", "Internal server error")])
                             }
                             FileHandlerServiceError::FileIsTooBig => 
-                                create_redirection_with_params("/home", &[("error", "File is too big")])
+                                create_redirection_with_params(destination, &[("error", "File is too big")])
                         }
                     },
                 };
@@ -77,19 +116,161 @@ async fn add_post(
     }
     match (user_name, content) {
         (Some(user_name), Some(content)) => {
-            match app_state.blog_post_service.add_post(user_name, content, user_avatar_url, post_image).await {
-                Ok(_) => Ok(Redirect::to("/home").into_response()),
+            let author = PostAuthor { user_name, user_avatar_url, user_email };
+            let result = match parent_id {
+                Some(parent_id) => app_state.blog_post_service
+                    .add_reply(parent_id, author, content, post_image, tags).await,
+                None => app_state.blog_post_service
+                    .add_post(author, content, post_image, tags).await,
+            };
+            match result {
+                Ok(_) => Ok(Redirect::to(destination).into_response()),
+                Err(AddingBlogPostError::ParentPostNotFound) => Err(StatusCode::NOT_FOUND),
+                Err(err @ (
+                    AddingBlogPostError::ReplyNestingTooDeep(_)
+                    | AddingBlogPostError::InvalidTag(_)
+                    | AddingBlogPostError::UserAvatarHasUnsupportedFormat
+                    | AddingBlogPostError::UserAvatarIsTooBig
+                    | AddingBlogPostError::UserAvatarIsInvalidPng(_)
+                    | AddingBlogPostError::UserAvatarHasInvalidHeader(_)
+                    | AddingBlogPostError::UserAvatarDimensionsTooBig { .. }
+                    | AddingBlogPostError::UserAvatarUrlIsInvalid
+                    | AddingBlogPostError::UserAvatarUrlHasUnsupportedScheme(_)
+                    | AddingBlogPostError::UserAvatarDomainIsNotAllowed(_)
+                    | AddingBlogPostError::UserAvatarHostCannotBeResolved(_)
+                    | AddingBlogPostError::UserAvatarAddressIsNotAllowed(_)
+                    | AddingBlogPostError::TooManyUserAvatarRedirects
+                    | AddingBlogPostError::UserAvatarNotFound
+                    | AddingBlogPostError::UserEmailIsInvalid
+                )) =>
+                    create_redirection_with_params(destination, &[("error", &err.to_string())]),
                 Err(err) => {
                     tracing::error!("Error adding post: {:?}", err);
-                    create_redirection_with_params(
-                        "/home",
-                        &[("error", "User avatar url does not lead to a PNG")])
+                    create_redirection_with_params(destination, &[("error", "Internal server error")])
+                },
+            }
+        },
+        (None, None) => create_redirection_with_params(destination, &[("error", "User name and content cannot be empty (or contain only whit spaces)")]),
+        (None, _) => create_redirection_with_params(destination, &[("error", "User name cannot be empty (or contain only whit spaces)")]),
+        (_, None) => create_redirection_with_params(destination, &[("error", "Content cannot be empty (or contain only whit spaces)")]),
+    }
+}
+
+async fn replace_post(
+    State(app_state): State<AppStateType>,
+    Path(id): Path<i64>,
+    req: Multipart
+) -> Result<StatusCode, StatusCode> {
+    update_post(app_state, id, req, true).await
+}
+
+async fn edit_post(
+    State(app_state): State<AppStateType>,
+    Path(id): Path<i64>,
+    req: Multipart
+) -> Result<StatusCode, StatusCode> {
+    update_post(app_state, id, req, false).await
+}
+
+// When `replace` is set fields missing from the request are cleared, otherwise they are left untouched
+async fn update_post(
+    app_state: AppStateType,
+    id: i64,
+    mut req: Multipart,
+    replace: bool,
+) -> Result<StatusCode, StatusCode> {
+    let mut content = None;
+    let (mut user_avatar_url, mut post_image) = match replace {
+        true => (ImageChange::Remove, ImageChange::Remove),
+        false => (ImageChange::Keep, ImageChange::Keep),
+    };
+    while let Ok(Some(field)) = req.next_field().await {
+        match field.name() {
+            Some("content") => content = get_field_text(field).await?,
+            Some("user_avatar_url") => user_avatar_url = match get_field_text(field).await? {
+                Some(user_avatar_url) => ImageChange::Replace(user_avatar_url),
+                None => ImageChange::Remove,
+            },
+            Some("remove_post_image") => post_image = ImageChange::Remove,
+            Some("post_image") => {
+                if let Some("") = field.file_name() {
+                    continue;
+                }
+                post_image = ImageChange::Replace(app_state.file_handler_service
+                    .save_file(field).await
+                    .map_err(|err| {
+                        use crate::services::file_handler_service::FileHandlerServiceError;
+                        match err {
+                            FileHandlerServiceError::UnsupportedFileFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                            FileHandlerServiceError::InvalidPngImage(_)
+                            | FileHandlerServiceError::InvalidImageHeader(_) => StatusCode::BAD_REQUEST,
+                            FileHandlerServiceError::ImageDimensionsTooBig { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                            FileHandlerServiceError::FileIsTooBig => StatusCode::PAYLOAD_TOO_LARGE,
+                            FileHandlerServiceError::SqlxError(_)
+                            | FileHandlerServiceError::TokioIoError(_)
+                            | FileHandlerServiceError::StorageFailed(_) => {
+                                tracing::error!("Error saving image: {:?}", err);
+                                StatusCode::INTERNAL_SERVER_ERROR
+                            },
+                        }
+                    })?);
+            },
+            _ => (),
+        }
+    }
+    if replace && content.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    app_state.blog_post_service.update_post(id, content, user_avatar_url, post_image).await
+        .map_err(|err| {
+            use crate::services::blog_post_service::UpdatingBlogPostError;
+            match err {
+                UpdatingBlogPostError::PostNotFound => StatusCode::NOT_FOUND,
+                UpdatingBlogPostError::AddingBlogPostError(
+                    AddingBlogPostError::SqlxError(_)
+                    | AddingBlogPostError::TokioIoError(_)
+                    | AddingBlogPostError::StorageFailed(_)
+                    | AddingBlogPostError::IdenticonRenderingFailed(_)
+                    | AddingBlogPostError::ReqwestError(_)
+                    | AddingBlogPostError::FailedToFetchUserAvatar
+                ) => {
+                    tracing::error!("Error updating post: {:?}", err);
+                    StatusCode::INTERNAL_SERVER_ERROR
                 },
+                UpdatingBlogPostError::AddingBlogPostError(
+                    AddingBlogPostError::UserAvatarHasUnsupportedFormat
+                    | AddingBlogPostError::UserAvatarNotFound
+                    | AddingBlogPostError::UserEmailIsInvalid
+                    | AddingBlogPostError::UserAvatarUrlIsInvalid
+                    | AddingBlogPostError::UserAvatarUrlHasUnsupportedScheme(_)
+                    | AddingBlogPostError::UserAvatarDomainIsNotAllowed(_)
+                    | AddingBlogPostError::UserAvatarHostCannotBeResolved(_)
+                    | AddingBlogPostError::UserAvatarAddressIsNotAllowed(_)
+                    | AddingBlogPostError::TooManyUserAvatarRedirects
+                    | AddingBlogPostError::UserAvatarIsTooBig
+                    | AddingBlogPostError::UserAvatarIsInvalidPng(_)
+                    | AddingBlogPostError::UserAvatarHasInvalidHeader(_)
+                    | AddingBlogPostError::UserAvatarDimensionsTooBig { .. }
+                    | AddingBlogPostError::ParentPostNotFound
+                    | AddingBlogPostError::ReplyNestingTooDeep(_)
+                    | AddingBlogPostError::InvalidTag(_)
+                ) => StatusCode::BAD_REQUEST,
             }
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_post(
+    State(app_state): State<AppStateType>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    match app_state.blog_post_service.delete_post(id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("Error deleting post: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         },
-        (None, None) => create_redirection_with_params("/home", &[("error", "User name and content cannot be empty (or contain only whit spaces)")]),
-        (None, _) => create_redirection_with_params("/home", &[("error", "User name cannot be empty (or contain only whit spaces)")]),
-        (_, None) => create_redirection_with_params("/home", &[("error", "Content cannot be empty (or contain only whit spaces)")]),
     }
 }
 
@@ -97,10 +278,11 @@ async fn add_post(
 struct GetPostsQuery {
     offset: Option<i64>,
     limit: Option<i64>,
+    tag: Option<String>,
 }
 
 async fn get_posts(State(app_state): State<AppStateType>, Query(pagination): Query<GetPostsQuery>) -> Result<Json<GetPostsResponse>, StatusCode> {
-    let posts = app_state.blog_post_service.get_posts(pagination.limit, pagination.offset).await
+    let posts = app_state.blog_post_service.get_posts(pagination.limit, pagination.offset, pagination.tag.as_deref()).await
         .map_err(|err| {
             tracing::error!("Error getting posts: {:?}", err);
             StatusCode::INTERNAL_SERVER_ERROR
@@ -108,6 +290,33 @@ async fn get_posts(State(app_state): State<AppStateType>, Query(pagination): Que
     Ok(Json(posts))
 }
 
+async fn get_post(
+    State(app_state): State<AppStateType>,
+    Path(id): Path<i64>,
+) -> Result<Json<crate::db::blog_posts::Post>, StatusCode> {
+    app_state.blog_post_service.get_post(id).await
+        .map_err(|err| {
+            tracing::error!("Error getting post: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_replies(
+    State(app_state): State<AppStateType>,
+    Path(id): Path<i64>,
+    Query(pagination): Query<GetPostsQuery>,
+) -> Result<Json<GetPostsResponse>, StatusCode> {
+    app_state.blog_post_service.get_replies(id, pagination.limit, pagination.offset).await
+        .map_err(|err| {
+            tracing::error!("Error getting replies: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
 async fn get_posts_all(
     State(app_state): State<AppStateType>
 ) -> Result<Json<Vec<crate::db::blog_posts::Post>>, StatusCode> {
@@ -118,3 +327,23 @@ async fn get_posts_all(
         })?;
     Ok(Json(posts))
 }
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct SearchPostsQuery {
+    q: String,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+async fn search_posts(
+    State(app_state): State<AppStateType>,
+    Query(query): Query<SearchPostsQuery>,
+) -> Result<Json<SearchPostsResponse>, StatusCode> {
+    app_state.blog_post_service.search_posts(&query.q, query.limit, query.offset).await
+        .map_err(|err| {
+            tracing::error!("Error searching posts: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::BAD_REQUEST)
+}
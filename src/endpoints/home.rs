@@ -1,20 +1,132 @@
-use axum::{extract::{Path, State}, response::IntoResponse, routing::get, Router};
+use axum::{extract::{Path, Query, State}, http::HeaderMap, response::{Html, IntoResponse}, routing::get, Router};
 use reqwest::StatusCode;
-use crate::app_state::AppStateType;
+use crate::{app_state::AppStateType, db::blog_posts::Post};
 
-use super::{static_files, RouterType};
+use super::{escape_html, static_files, RouterType};
 
 #[inline]
 pub(super) fn initialize() -> RouterType {
     Router::new()
         .route("/home", get(home))
+        .route("/home/post/:id", get(post_permalink))
         .route("/favicon.ico", get(favicon))
 }
 
-async fn home(state: State<AppStateType>) -> Result<impl IntoResponse, StatusCode> {
-    static_files::get_static_file(state, Path("index.html".to_string())).await
+async fn home(state: State<AppStateType>, headers: HeaderMap) -> Result<impl IntoResponse, StatusCode> {
+    static_files::get_static_file(state, Path("index.html".to_string()), headers).await
 }
 
-async fn favicon(state: State<AppStateType>) -> Result<impl IntoResponse, StatusCode> {
-    static_files::get_static_file(state, Path("favicon.ico".to_string())).await
+async fn favicon(state: State<AppStateType>, headers: HeaderMap) -> Result<impl IntoResponse, StatusCode> {
+    static_files::get_static_file(state, Path("favicon.ico".to_string()), headers).await
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct PostPermalinkQuery {
+    error: Option<String>,
+}
+
+async fn post_permalink(
+    State(app_state): State<AppStateType>,
+    Path(id): Path<i64>,
+    Query(query): Query<PostPermalinkQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let map_err = |err| {
+        tracing::error!("Error getting post: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let post = app_state.blog_post_service.get_post(id).await
+        .map_err(map_err)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let replies = app_state.blog_post_service.get_replies(id, Some(100), None).await
+        .map_err(map_err)?
+        .map(|v| v.posts)
+        .unwrap_or_default();
+    Ok(Html(render_post_page(&post, &replies, query.error.as_deref())))
+}
+
+// Mirrors the markup produced by `display_posts` in script.js so the same stylesheet applies
+fn render_article(post: &Post) -> String {
+    let user_name = escape_html(&post.user_name);
+    let user_avatar = post.user_avatar.as_deref()
+        .map(|v| format!(r#"<img src="/image/{}" alt="User avatar image">"#, escape_html(v)))
+        .unwrap_or_default();
+    let post_image = post.post_image.as_deref()
+        .map(|v| format!(r#"<img src="/image/{}" alt="Posted image">"#, escape_html(v)))
+        .unwrap_or_default();
+    let edited = post.updated_at
+        .map(|v| format!(" (edited: {})", v.format("%Y-%m-%d %H:%M:%S UTC")))
+        .unwrap_or_default();
+    format!(r#"<article>
+            <div>
+                <header>
+                    {user_avatar}
+                    <div>
+                        <b>{user_name}</b> <i><a href="/home/post/{id}">date: {date}{edited}</a> replies: {reply_count}</i>
+                    </div>
+                </header>
+                <p>
+                    {content}
+                </p>
+            </div>
+            {post_image}
+        </article>"#,
+        id = post.id,
+        date = post.publication_date.format("%Y-%m-%d %H:%M:%S UTC"),
+        reply_count = post.reply_count,
+        content = escape_html(&post.content),
+    )
+}
+
+fn render_post_page(post: &Post, replies: &[Post], error: Option<&str>) -> String {
+    let back_link = match post.parent_id {
+        Some(parent_id) => format!(r#"<a href="/home/post/{}">Back to the replied post</a>"#, parent_id),
+        None => r#"<a href="/home">Back to all posts</a>"#.to_string(),
+    };
+    let error = error
+        .map(|v| format!(r#"<div id="error-field">
+                <h2>Error</h2>
+                <p id="error-field-message">{}</p>
+            </div>"#, escape_html(v)))
+        .unwrap_or_default();
+    let replies = replies.iter()
+        .map(render_article)
+        .collect::<Vec<_>>()
+        .join("\n        ");
+    format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{user_name} - Rust web exercise</title>
+    <link rel="stylesheet" href="/file/style.css">
+</head>
+<body>
+    <main>
+        {back_link}
+        {article}
+        <form method="post" action="/post/{id}/replies" enctype="multipart/form-data">
+            <label for="user_name">Username:</label>
+            <input type="text" name="user_name" id="user_name">
+            <label for="content">Reply:</label>
+            <textarea type="text" name="content" id="content"></textarea>
+            <label for="user_avatar_url">User avatar url:</label>
+            <input name="user_avatar_url" id="user_avatar_url">
+            <label for="user_email">Email (only used to find your Gravatar):</label>
+            <input type="email" name="user_email" id="user_email">
+            <label for="post_image">Image:</label>
+            <input type="file" name="post_image" id="post_image" accept="image/png,image/jpeg,image/gif,image/webp">
+            {error}
+            <input type="submit" value="Reply">
+        </form>
+        <section>
+        {replies}
+        </section>
+    </main>
+</body>
+</html>
+"#,
+        user_name = escape_html(&post.user_name),
+        article = render_article(post),
+        id = post.id,
+    )
 }
@@ -1,35 +1,152 @@
-use axum::{body::Body, extract::{Path, State}, response::IntoResponse, routing::get, Router};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, HeaderValue}, response::{IntoResponse, Response}, routing::get, Router};
 use reqwest::StatusCode;
-use super::{AppStateType, RouterType};
+use crate::services::storage::StorageError;
+use super::{partial_content::build_file_response, AppStateType, RouterType};
+
+// Stored files never change as they are named after a random UUID and deduplicated by their hash
+const CACHE_CONTROL: HeaderValue = HeaderValue::from_static("public, max-age=31536000, immutable");
+// Which variant a width selects depends on the configured widths, so variant urls are cached for a limited time only
+const VARIANT_CACHE_CONTROL: HeaderValue = HeaderValue::from_static("public, max-age=86400");
+// Variants are generated in the background, the original served in the meantime has to be revalidated
+const FALLBACK_CACHE_CONTROL: HeaderValue = HeaderValue::from_static("no-cache");
 
 #[inline]
 pub(super) fn initialize() -> RouterType {
     Router::new()
         .route("/:uuid", get(get_image))
+        .route("/:uuid/thumb", get(get_thumbnail))
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct GetImageQuery {
+    w: Option<u32>,
+}
+
+#[inline]
+fn map_sqlx_error(err: sqlx::Error) -> StatusCode {
+    tracing::error!("Error getting image: {:?}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
 }
 
+#[inline]
+fn map_storage_error(err: StorageError) -> StatusCode {
+    match err {
+        StorageError::NotFound => StatusCode::NOT_FOUND,
+        StorageError::InvalidKey => StatusCode::FORBIDDEN,
+        StorageError::TokioIoError(_) | StorageError::ObjectStoreError(_) => {
+            tracing::error!("Error getting image: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+    }
+}
+
+/// Serves the narrowest variant at least `w` pixels wide, falls back to the original when there is none
 async fn get_image(
-    State(app_state): State<AppStateType>, Path(uuid): Path<String>
+    State(app_state): State<AppStateType>,
+    Path(uuid): Path<String>,
+    Query(query): Query<GetImageQuery>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let mut response = Body::from_stream(app_state.file_handler_service.get_file(&uuid).await
-    .map_err(|err| {
-        use crate::services::file_handler_service::GetFileFromDirectoryError;
-        match err {
-            GetFileFromDirectoryError::FileNotFound => StatusCode::NOT_FOUND,
-            GetFileFromDirectoryError::PathNotInAllowedDirectory => StatusCode::FORBIDDEN,
-            GetFileFromDirectoryError::TokioIoError(_) => {
-                tracing::error!("Error getting image: {:?}", err);
-                StatusCode::INTERNAL_SERVER_ERROR
-            },
+    get_image_or_variant(app_state, uuid, query.w, headers).await
+}
+
+/// Serves the smallest variant of the image, falls back to the original when there is none
+async fn get_thumbnail(
+    State(app_state): State<AppStateType>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    get_image_or_variant(app_state, uuid, Some(0), headers).await
+}
+
+async fn get_image_or_variant(
+    app_state: AppStateType, uuid: String, min_width: Option<u32>, headers: HeaderMap
+) -> Result<Response, StatusCode> {
+    // Only files which have been saved are served, files of uploads which are still in progress have no row yet
+    let image = app_state.file_handler_service.get_image(&uuid).await
+        .map_err(map_sqlx_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let variant = match min_width {
+        Some(min_width) => app_state.file_handler_service.get_image_variant(&uuid, min_width).await
+            .map_err(map_sqlx_error)?,
+        None => None,
+    };
+    let hash = image.get_hash().iter()
+        .map(|v| format!("{:02x}", v))
+        .collect::<String>();
+    match variant {
+        Some(variant) => {
+            let etag = format!("\"{}-{}\"", hash, variant.get_width());
+            serve_file(&app_state, variant.get_filename(), variant.get_mime_type(), &etag, VARIANT_CACHE_CONTROL, &headers).await
+        },
+        None => {
+            let cache_control = match min_width {
+                Some(_) => FALLBACK_CACHE_CONTROL,
+                None => CACHE_CONTROL,
+            };
+            serve_file(&app_state, image.get_filename(), image.get_mime_type(), &format!("\"{}\"", hash), cache_control, &headers).await
+        },
+    }
+}
+
+// Weak comparison is used as it is what If-None-Match requires
+fn is_etag_matching(if_none_match: &HeaderValue, etag: &str) -> bool {
+    if_none_match.to_str()
+        .map(|v| v.split(',').map(str::trim).any(|v| v == "*" || v.trim_start_matches("W/") == etag))
+        .unwrap_or(false)
+}
+
+// HTTP dates have a resolution of one second
+fn is_modified_since(if_modified_since: &HeaderValue, last_modified: SystemTime) -> bool {
+    let Some(if_modified_since) = if_modified_since.to_str().ok()
+        .and_then(|v| httpdate::parse_http_date(v).ok()) else {
+        return true;
+    };
+    let last_modified = last_modified.duration_since(UNIX_EPOCH)
+        .map(|v| UNIX_EPOCH + Duration::from_secs(v.as_secs()))
+        .unwrap_or(UNIX_EPOCH);
+    last_modified > if_modified_since
+}
+
+async fn serve_file(
+    app_state: &AppStateType,
+    filename: &str,
+    mime_type: &str,
+    etag: &str,
+    cache_control: HeaderValue,
+    request_headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let map_header_err = |_| {
+        tracing::error!("Error creating response header");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, etag.parse().map_err(map_header_err)?);
+    headers.insert(header::CACHE_CONTROL, cache_control);
+    // If-Modified-Since is ignored when If-None-Match is present
+    if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+        if is_etag_matching(if_none_match, etag) {
+            return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+        }
+    }
+    let metadata = app_state.file_handler_service.get_file_metadata(filename).await
+        .map_err(map_storage_error)?;
+    headers.insert(
+        header::LAST_MODIFIED,
+        httpdate::fmt_http_date(metadata.last_modified).parse().map_err(map_header_err)?,
+    );
+    if let (None, Some(if_modified_since)) = (
+        request_headers.get(header::IF_NONE_MATCH),
+        request_headers.get(header::IF_MODIFIED_SINCE),
+    ) {
+        if !is_modified_since(if_modified_since, metadata.last_modified) {
+            return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
         }
-    })?).into_response();
-    response.headers_mut()
-        .insert(
-            "Content-Type",
-            "image/png".parse().map_err(|_| {
-                tracing::error!("Error creating response header");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-        );
-    Ok(response)
+    }
+    headers.insert(header::CONTENT_TYPE, mime_type.parse().map_err(map_header_err)?);
+    build_file_response(request_headers, headers, metadata.size, |range| async move {
+        app_state.file_handler_service.get_file(filename, range).await
+            .map_err(map_storage_error)
+    }).await
 }
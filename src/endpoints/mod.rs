@@ -1,19 +1,38 @@
 pub(crate) mod models;
 mod blog_posts;
 mod images;
+mod partial_content;
 mod home;
 mod static_files;
+mod tags;
 use axum::Router;
 use crate::{app_state::AppStateType, env_variables};
 
 pub(super) type RouterType = Router<AppStateType>;
 
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 pub(super) async fn start_server(app_state: AppStateType) -> Result<(), Box<dyn std::error::Error>> {
     let router = Router::new()
         .nest("/post", blog_posts::initialize(
-            env_variables::get_env_var(env_variables::MAX_BODY_SIZE)?.parse()?))
+            env_variables::get_env_var(env_variables::MAX_BODY_SIZE)?.parse()?,
+            env_variables::get_env_var(env_variables::COMPRESSION_MIN_SIZE)?.parse()?))
         .nest("/image", images::initialize())
         .nest("/file", static_files::initialize())
+        .nest("/tags", tags::initialize())
         .nest("/", home::initialize())
         .with_state(app_state);
 
@@ -1 +1,2 @@
 pub(crate) mod get_posts_response;
+pub(crate) mod search_posts_response;
@@ -0,0 +1,9 @@
+use crate::db::blog_posts::SearchResult;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct SearchPostsResponse {
+    pub limit: i64,
+    pub offset: i64,
+    pub total: i64,
+    pub results: Vec<SearchResult>,
+}
@@ -0,0 +1,87 @@
+use std::future::Future;
+use axum::{body::{Body, Bytes}, http::{header, HeaderMap, HeaderValue}, response::{IntoResponse, Response}};
+use futures::{stream, StreamExt};
+use reqwest::StatusCode;
+use crate::services::{byte_range::{parse_range_header, ByteRange, RangeRequest}, storage::ByteStream};
+
+// Range is only honoured if If-Range matches the current representation, weak ETags never match
+fn is_if_range_matching(request_headers: &HeaderMap, headers: &HeaderMap) -> bool {
+    let Some(if_range) = request_headers.get(header::IF_RANGE) else {
+        return true;
+    };
+    if if_range.as_bytes().starts_with(b"W/") {
+        return false;
+    }
+    [header::ETAG, header::LAST_MODIFIED].iter()
+        .filter_map(|v| headers.get(v))
+        .any(|v| v == if_range)
+}
+
+#[inline]
+fn get_content_range(range: ByteRange, size: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end, size)
+}
+
+/// Builds the response with the whole file or the requested ranges of it.
+/// `headers` should already contain the headers describing the whole file (e.g. `Content-Type`, `ETag`),
+/// `get_content` is called with the range which should be streamed or `None` for the whole file
+pub(super) async fn build_file_response<E, F, Fut>(
+    request_headers: &HeaderMap,
+    mut headers: HeaderMap,
+    size: u64,
+    get_content: F,
+) -> Result<Response, E>
+where
+    F: Fn(Option<ByteRange>) -> Fut,
+    Fut: Future<Output = Result<ByteStream, E>>,
+{
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let range_request = match request_headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) if is_if_range_matching(request_headers, &headers) => parse_range_header(range, size),
+        _ => RangeRequest::Full,
+    };
+    let ranges = match range_request {
+        RangeRequest::Full => {
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+            return Ok((headers, Body::from_stream(get_content(None).await?)).into_response());
+        },
+        RangeRequest::NotSatisfiable => {
+            headers.remove(header::CONTENT_TYPE);
+            headers.insert(header::CONTENT_RANGE, format!("bytes */{}", size).parse().expect("Valid header value"));
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        },
+        RangeRequest::Partial(ranges) => ranges,
+    };
+    if let [range] = ranges[..] {
+        headers.insert(header::CONTENT_RANGE, get_content_range(range, size).parse().expect("Valid header value"));
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(range.len()));
+        return Ok((StatusCode::PARTIAL_CONTENT, headers, Body::from_stream(get_content(Some(range)).await?)).into_response());
+    }
+
+    // Every range gets its own part of a multipart/byteranges body
+    let boundary = uuid::Uuid::new_v4().simple().to_string();
+    let content_type = headers.remove(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok().map(|v| format!("Content-Type: {}\r\n", v)))
+        .unwrap_or_default();
+    let mut parts: Vec<ByteStream> = Vec::with_capacity(ranges.len() * 2 + 1);
+    let mut content_length = 0;
+    for (i, range) in ranges.iter().enumerate() {
+        let part_headers = format!(
+            "{}--{}\r\n{}Content-Range: {}\r\n\r\n",
+            if i == 0 { "" } else { "\r\n" }, boundary, content_type, get_content_range(*range, size),
+        );
+        content_length += part_headers.len() as u64 + range.len();
+        parts.push(Box::pin(stream::once(async { Ok(Bytes::from(part_headers)) })));
+        parts.push(get_content(Some(*range)).await?);
+    }
+    let closing_boundary = format!("\r\n--{}--\r\n", boundary);
+    content_length += closing_boundary.len() as u64;
+    parts.push(Box::pin(stream::once(async { Ok(Bytes::from(closing_boundary)) })));
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        format!("multipart/byteranges; boundary={}", boundary).parse().expect("Valid header value"),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    Ok((StatusCode::PARTIAL_CONTENT, headers, Body::from_stream(stream::iter(parts).flatten())).into_response())
+}
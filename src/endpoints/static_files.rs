@@ -1,24 +1,92 @@
-use axum::{body::Body, extract::{Path, State}, response::IntoResponse, routing::get, Router};
+use axum::{body::Body, extract::{Path, State}, http::{header, HeaderMap, HeaderValue}, response::{IntoResponse, Response}, routing::get, Router};
+use futures::stream;
 use reqwest::StatusCode;
-use crate::app_state::AppStateType;
-use super::RouterType;
+use crate::{app_state::AppStateType, services::{content_encoding::ContentEncoding, file_handler_service::GetFileFromDirectoryError}};
+use super::{partial_content::build_file_response, RouterType};
 
 #[inline]
 pub(super) fn initialize() -> RouterType {
     Router::new()
-        .route("/*path", get(get_static_file))
+        .route("/*path", get(get_static_file).head(head_static_file))
 }
 
-pub(super) async fn get_static_file(State(app_state): State<AppStateType>, Path(path): Path<String>) -> Result<impl IntoResponse, StatusCode> {
-    use crate::services::file_handler_service::GetFileFromDirectoryError;
-    Ok(Body::from_stream(app_state.static_files_service.get_static_file(&path).await
-        .map_err(|err| match err {
-            GetFileFromDirectoryError::FileNotFound => StatusCode::NOT_FOUND,
-            GetFileFromDirectoryError::PathNotInAllowedDirectory => StatusCode::FORBIDDEN,
-            GetFileFromDirectoryError::TokioIoError(_) => {
-                tracing::error!("Error getting file: {:?}", err);
-                StatusCode::INTERNAL_SERVER_ERROR
+#[inline]
+fn map_get_file_error(err: GetFileFromDirectoryError) -> StatusCode {
+    match err {
+        GetFileFromDirectoryError::FileNotFound => StatusCode::NOT_FOUND,
+        GetFileFromDirectoryError::PathNotInAllowedDirectory => StatusCode::FORBIDDEN,
+        GetFileFromDirectoryError::TokioIoError(_) => {
+            tracing::error!("Error getting file: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+    }
+}
+
+pub(super) async fn get_static_file(
+    State(app_state): State<AppStateType>,
+    Path(path): Path<String>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    serve_static_file(app_state, path, request_headers, true).await
+}
+
+/// Same headers as GET without opening the file
+async fn head_static_file(
+    State(app_state): State<AppStateType>,
+    Path(path): Path<String>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    serve_static_file(app_state, path, request_headers, false).await
+}
+
+async fn serve_static_file(
+    app_state: AppStateType, path: String, request_headers: HeaderMap, with_body: bool
+) -> Result<Response, StatusCode> {
+    let size = app_state.static_files_service.get_static_file_size(&path).await
+        .map_err(map_get_file_error)?;
+    let mime_type = app_state.static_files_service.get_static_file_mime_type(&path).await
+        .map_err(map_get_file_error)?;
+    let encodings = request_headers.get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(ContentEncoding::parse_accept_encoding)
+        .unwrap_or_default();
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, mime_type.parse().map_err(|_| {
+        tracing::error!("Error creating response header");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?);
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+    let precompressed_file = app_state.static_files_service.get_precompressed_file(&path, &encodings).await
+        .map_err(map_get_file_error)?;
+    let (path, size) = match precompressed_file {
+        Some((encoding, compressed_path, compressed_size)) => {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.get_name()));
+            (compressed_path, compressed_size)
+        },
+        None => match encodings.first() {
+            // Ranges of a stream compressed on the fly cannot be served so they are not advertised
+            Some(encoding) if app_state.static_files_service.is_compressible(&mime_type, size) => {
+                headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.get_name()));
+                let content = match with_body {
+                    true => app_state.static_files_service.get_compressed_static_file(&path, *encoding).await
+                        .map_err(map_get_file_error)?,
+                    false => Box::pin(stream::empty()),
+                };
+                return Ok((headers, Body::from_stream(content)).into_response());
             },
-        })?
-    ))
-}
\ No newline at end of file
+            _ => (path, size),
+        },
+    };
+    build_file_response(&request_headers, headers, size, |range| {
+        let (app_state, path) = (&app_state, &path);
+        async move {
+            if !with_body {
+                return Ok(Box::pin(stream::empty()) as _);
+            }
+            app_state.static_files_service.get_static_file(path, range).await
+                .map_err(map_get_file_error)
+        }
+    }).await
+}
@@ -0,0 +1,19 @@
+use axum::{extract::State, routing::get, Json, Router};
+use reqwest::StatusCode;
+use crate::{app_state::AppStateType, db::tags::TagCount};
+use super::RouterType;
+
+#[inline]
+pub(super) fn initialize() -> RouterType {
+    Router::new()
+        .route("/", get(get_tags))
+}
+
+async fn get_tags(State(app_state): State<AppStateType>) -> Result<Json<Vec<TagCount>>, StatusCode> {
+    let tags = app_state.blog_post_service.get_tags().await
+        .map_err(|err| {
+            tracing::error!("Error getting tags: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(tags))
+}
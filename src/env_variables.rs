@@ -4,6 +4,34 @@ pub(crate) const MAX_BODY_SIZE: &str = "MAX_BODY_SIZE";
 pub(crate) const DATABASE_URL: &str = "DATABASE_URL";
 pub(crate) const STATIC_FILES_DIRECTORY: &str = "STATIC_FILES_DIRECTORY";
 pub(crate) const ADDRESS: &str = "ADDRESS";
+pub(crate) const MAX_REPLY_DEPTH: &str = "MAX_REPLY_DEPTH";
+pub(crate) const MAX_IMAGE_WIDTH: &str = "MAX_IMAGE_WIDTH";
+pub(crate) const MAX_IMAGE_HEIGHT: &str = "MAX_IMAGE_HEIGHT";
+pub(crate) const MAX_IMAGE_PIXELS: &str = "MAX_IMAGE_PIXELS";
+pub(crate) const IMAGE_VARIANT_WIDTHS: &str = "IMAGE_VARIANT_WIDTHS";
+pub(crate) const PRESERVED_IMAGE_METADATA: &str = "PRESERVED_IMAGE_METADATA";
+pub(crate) const IMAGE_GC_INTERVAL: &str = "IMAGE_GC_INTERVAL";
+pub(crate) const IMAGE_GC_GRACE_PERIOD: &str = "IMAGE_GC_GRACE_PERIOD";
+pub(crate) const STAGING_FILE_MAX_AGE: &str = "STAGING_FILE_MAX_AGE";
+pub(crate) const STORAGE_BACKEND: &str = "STORAGE_BACKEND";
+pub(crate) const S3_BUCKET: &str = "S3_BUCKET";
+pub(crate) const S3_REGION: &str = "S3_REGION";
+pub(crate) const S3_ENDPOINT: &str = "S3_ENDPOINT";
+pub(crate) const S3_ACCESS_KEY_ID: &str = "S3_ACCESS_KEY_ID";
+pub(crate) const S3_SECRET_ACCESS_KEY: &str = "S3_SECRET_ACCESS_KEY";
+pub(crate) const COMPRESSION_MIN_SIZE: &str = "COMPRESSION_MIN_SIZE";
+pub(crate) const AVATAR_ALLOWED_DOMAINS: &str = "AVATAR_ALLOWED_DOMAINS";
+pub(crate) const MAX_AVATAR_SIZE: &str = "MAX_AVATAR_SIZE";
+pub(crate) const REMOTE_IMAGE_TTL: &str = "REMOTE_IMAGE_TTL";
+pub(crate) const HTTP_CONNECT_TIMEOUT: &str = "HTTP_CONNECT_TIMEOUT";
+pub(crate) const HTTP_READ_TIMEOUT: &str = "HTTP_READ_TIMEOUT";
+pub(crate) const HTTP_TIMEOUT: &str = "HTTP_TIMEOUT";
+pub(crate) const HTTP_MAX_REDIRECTS: &str = "HTTP_MAX_REDIRECTS";
+pub(crate) const HTTP_USER_AGENT: &str = "HTTP_USER_AGENT";
+pub(crate) const AVATAR_WORKERS: &str = "AVATAR_WORKERS";
+pub(crate) const AVATAR_JOB_MAX_ATTEMPTS: &str = "AVATAR_JOB_MAX_ATTEMPTS";
+pub(crate) const AVATAR_JOB_RETRY_DELAY: &str = "AVATAR_JOB_RETRY_DELAY";
+pub(crate) const AVATAR_JOB_TIMEOUT: &str = "AVATAR_JOB_TIMEOUT";
 
 #[derive(Debug, thiserror::Error)]
 #[error("Invalid environment variable {name} - {error}")]
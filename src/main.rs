@@ -4,6 +4,7 @@ mod db;
 mod services;
 mod env_variables;
 
+use std::sync::Arc;
 use endpoints::start_server;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use app_state::AppState;
@@ -58,6 +59,24 @@ async fn main() {
             return;
         },
     };
+    match std::env::args().nth(1).as_deref() {
+        None => (),
+        Some("gc") => {
+            let dry_run = std::env::args().skip(2).any(|v| v == "--dry-run");
+            match app_state.image_garbage_collector.collect_garbage(dry_run).await {
+                Ok(report) => print!("{}", report),
+                Err(err) => tracing::error!("Image garbage collection failed: {}", err),
+            }
+            connection_pool.close().await;
+            return;
+        },
+        Some(command) => {
+            tracing::error!("Unknown command {}, the only supported command is: gc [--dry-run]", command);
+            return;
+        },
+    }
+    app_state.image_garbage_collector.start();
+    app_state.avatar_job_queue.start(Arc::downgrade(&app_state));
     if let Err(err) = start_server(app_state).await {
         tracing::error!("Error while running server: {}", err);
         return;
@@ -0,0 +1,285 @@
+use std::{net::{IpAddr, Ipv4Addr, SocketAddr}, sync::Arc};
+use reqwest::{dns::{Addrs, Name, Resolve, Resolving}, header, StatusCode, Url};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum AvatarFetcherError {
+    #[error("Request failed: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("Invalid url")]
+    InvalidUrl,
+    #[error("Unsupported scheme {0}")]
+    UnsupportedScheme(String),
+    #[error("Domain {0} is not allowed")]
+    DomainNotAllowed(String),
+    #[error("Host {0} cannot be resolved")]
+    HostNotResolved(String),
+    #[error("Address {0} is not allowed")]
+    AddressNotAllowed(IpAddr),
+    #[error("Too many redirects")]
+    TooManyRedirects,
+    #[error("Response is too big ({0} bytes)")]
+    TooBig(u64),
+    #[error("Unsuccessful response status {0}")]
+    UnsuccessfulStatus(StatusCode),
+}
+
+/// Returns false for loopback, private, link-local, multicast and other addresses which are not publicly routable
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, third, _] = address.octets();
+            !(address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_multicast()
+                || address.is_broadcast()
+                || address.is_documentation()
+                // "This network" 0.0.0.0/8 and shared address space 100.64.0.0/10
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64)
+                // IETF protocol assignments 192.0.0.0/24, benchmarking 198.18.0.0/15 and reserved 240.0.0.0/4
+                || (first == 192 && second == 0 && third == 0)
+                || (first == 198 && second & 0xfe == 18)
+                || first >= 240)
+        },
+        IpAddr::V6(address) => match address.segments() {
+            // IPv4-compatible ::/96 (including :: and ::1), IPv4-mapped ::ffff:0:0/96, NAT64 64:ff9b::/96
+            // and 6to4 2002::/16 addresses reach the embedded IPv4 address
+            [0, 0, 0, 0, 0, 0, high, low]
+            | [0, 0, 0, 0, 0, 0xffff, high, low]
+            | [0x64, 0xff9b, 0, 0, 0, 0, high, low]
+            | [0x2002, high, low, ..] => is_public_address(IpAddr::V4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))),
+            segments => !(address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                || address.is_unique_local()
+                || address.is_unicast_link_local()
+                // Deprecated site-local fec0::/10, local-use NAT64 64:ff9b:1::/48, Teredo 2001::/32 and documentation 2001:db8::/32
+                || segments[0] & 0xffc0 == 0xfec0
+                || segments[..3] == [0x64, 0xff9b, 1]
+                || segments[..2] == [0x2001, 0]
+                || segments[..2] == [0x2001, 0xdb8]),
+        },
+    }
+}
+
+async fn resolve_host(host: &str, port: u16) -> Result<Vec<SocketAddr>, AvatarFetcherError> {
+    let addresses = tokio::net::lookup_host((host, port)).await
+        .map_err(|_| AvatarFetcherError::HostNotResolved(host.to_string()))?
+        .collect::<Vec<_>>();
+    match addresses.is_empty() {
+        true => Err(AvatarFetcherError::HostNotResolved(host.to_string())),
+        false => Ok(addresses),
+    }
+}
+
+/// Resolver for HTTP clients, drops non-public addresses in case the host resolves differently than when it was checked
+#[derive(Debug)]
+pub(crate) struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses = resolve_host(name.as_str(), 0).await?;
+            let public_addresses = addresses.iter()
+                .copied()
+                .filter(|v| is_public_address(v.ip()))
+                .collect::<Vec<_>>();
+            match public_addresses.is_empty() {
+                true => Err(AvatarFetcherError::AddressNotAllowed(addresses[0].ip()).into()),
+                false => Ok(Box::new(public_addresses.into_iter()) as Addrs),
+            }
+        })
+    }
+}
+
+/// Downloads avatars from user supplied urls without letting them reach internal hosts
+#[derive(Debug, Clone)]
+pub(crate) struct AvatarFetcher {
+    // Empty allows any domain, otherwise the host has to be one of them or their subdomain
+    allowed_domains: Arc<[String]>,
+    max_redirects: usize,
+    max_avatar_size: usize,
+}
+
+impl AvatarFetcher {
+    #[inline]
+    pub(crate) fn new(allowed_domains: Arc<[String]>, max_redirects: usize, max_avatar_size: usize) -> Self {
+        Self { allowed_domains, max_redirects, max_avatar_size }
+    }
+
+    #[inline]
+    pub(crate) fn get_max_avatar_size(&self) -> usize {
+        self.max_avatar_size
+    }
+
+    fn is_domain_allowed(&self, host: &str) -> bool {
+        self.allowed_domains.is_empty() || self.allowed_domains.iter()
+            .any(|domain| host.eq_ignore_ascii_case(domain)
+                || host.len() > domain.len()
+                    && host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase())))
+    }
+
+    /// Checks everything which does not require resolving the host, returns the host if it still has to be resolved
+    fn check_url_statically<'a>(&self, url: &'a Url) -> Result<Option<&'a str>, AvatarFetcherError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AvatarFetcherError::UnsupportedScheme(url.scheme().to_string()));
+        }
+        let host = url.host_str().ok_or(AvatarFetcherError::InvalidUrl)?;
+        if !self.is_domain_allowed(host) {
+            return Err(AvatarFetcherError::DomainNotAllowed(host.to_string()));
+        }
+        // IPv6 hosts are enclosed in brackets
+        match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(address) if !is_public_address(address) => Err(AvatarFetcherError::AddressNotAllowed(address)),
+            Ok(_) => Ok(None),
+            Err(_) => Ok(Some(host)),
+        }
+    }
+
+    async fn check_url(&self, url: &Url) -> Result<(), AvatarFetcherError> {
+        let Some(host) = self.check_url_statically(url)? else {
+            return Ok(());
+        };
+        let addresses = resolve_host(host, url.port_or_known_default().unwrap_or_default()).await?;
+        match addresses.into_iter().map(|v| v.ip()).find(|v| !is_public_address(*v)) {
+            Some(address) => Err(AvatarFetcherError::AddressNotAllowed(address)),
+            None => Ok(()),
+        }
+    }
+
+    /// Addresses refused by `PublicAddressResolver` surface wrapped in the request error
+    fn map_request_error(err: reqwest::Error) -> AvatarFetcherError {
+        let mut source = std::error::Error::source(&err);
+        while let Some(inner) = source {
+            if let Some(AvatarFetcherError::AddressNotAllowed(address)) = inner.downcast_ref::<AvatarFetcherError>() {
+                return AvatarFetcherError::AddressNotAllowed(*address);
+            }
+            source = inner.source();
+        }
+        err.into()
+    }
+
+    /// Rejects urls which can never be fetched without making any network requests,
+    /// the host is still checked again when the avatar is fetched
+    pub(crate) fn validate_url(&self, url: &str) -> Result<(), AvatarFetcherError> {
+        let url = Url::parse(url).map_err(|_| AvatarFetcherError::InvalidUrl)?;
+        self.check_url_statically(&url)?;
+        Ok(())
+    }
+
+    /// Returns the successful response, every redirect target is checked before it is requested.
+    /// Responses declaring a length above the avatar size limit are rejected before their body is read,
+    /// the limit still has to be enforced while reading as the declared length does not have to be true.
+    /// If validators of a previously fetched response are given the request is conditional
+    /// and a `304 Not Modified` response can be returned as well.
+    /// The client is expected not to follow redirects and to resolve hosts with `PublicAddressResolver`
+    pub(crate) async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<reqwest::Response, AvatarFetcherError> {
+        let mut url = Url::parse(url).map_err(|_| AvatarFetcherError::InvalidUrl)?;
+        for _ in 0..=self.max_redirects {
+            self.check_url(&url).await?;
+            let mut request = client.get(url.clone());
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+            let response = request.send().await.map_err(Self::map_request_error)?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(response);
+            }
+            if !response.status().is_redirection() {
+                if !response.status().is_success() {
+                    return Err(AvatarFetcherError::UnsuccessfulStatus(response.status()));
+                }
+                return match response.content_length() {
+                    Some(length) if length > self.max_avatar_size as u64 => Err(AvatarFetcherError::TooBig(length)),
+                    _ => Ok(response),
+                };
+            }
+            let location = response.headers().get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or(AvatarFetcherError::InvalidUrl)?;
+            url = url.join(location).map_err(|_| AvatarFetcherError::InvalidUrl)?;
+        }
+        Err(AvatarFetcherError::TooManyRedirects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_public_ipv4_addresses_are_rejected() {
+        for address in [
+            "0.0.0.0", "0.1.2.3", "10.0.0.1", "100.64.0.1", "100.127.255.255", "127.0.0.1", "169.254.169.254",
+            "172.16.0.1", "172.31.255.255", "192.0.0.8", "192.0.2.1", "192.168.1.1", "198.18.0.1", "198.19.255.255",
+            "198.51.100.1", "203.0.113.1", "224.0.0.1", "239.255.255.255", "240.0.0.1", "255.255.255.255",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn public_ipv4_addresses_are_accepted() {
+        for address in ["1.1.1.1", "8.8.8.8", "100.63.255.255", "100.128.0.1", "172.32.0.1", "192.0.1.1", "198.17.255.255", "198.20.0.1"] {
+            assert!(is_public_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn non_public_ipv6_addresses_are_rejected() {
+        for address in [
+            "::", "::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1", "::ffff:169.254.169.254", "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe", "64:ff9b::c0a8:101", "64:ff9b:1::808:808", "2002:7f00:1::", "2002:a00:1::1",
+            "2002:c0a8:101::", "::7f00:1", "::a00:1", "2001::1", "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+            "2001:db8::1", "2001:db8:ffff::1", "fc00::1", "fd12:3456::1", "fe80::1", "fec0::1", "feff::1", "ff02::1",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn public_ipv6_addresses_are_accepted() {
+        for address in ["2606:4700:4700::1111", "2001:4860:4860::8888", "::808:808", "::ffff:8.8.8.8", "64:ff9b::808:808", "2002:808:808::1"] {
+            assert!(is_public_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn urls_with_non_public_address_literals_are_rejected() {
+        let fetcher = AvatarFetcher::new(Arc::new([]), 0, 0);
+        for url in ["http://[::1]/a.png", "http://[::ffff:127.0.0.1]/a.png", "http://127.0.0.1/a.png", "https://[64:ff9b::a00:1]/"] {
+            assert!(matches!(fetcher.validate_url(url), Err(AvatarFetcherError::AddressNotAllowed(_))), "{}", url);
+        }
+        assert!(fetcher.validate_url("http://[2606:4700:4700::1111]/a.png").is_ok());
+    }
+
+    #[tokio::test]
+    async fn addresses_refused_by_the_resolver_are_not_allowed() {
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(PublicAddressResolver))
+            .build()
+            .unwrap();
+        let err = client.get("http://localhost:1/").send().await.unwrap_err();
+        assert!(matches!(AvatarFetcher::map_request_error(err), AvatarFetcherError::AddressNotAllowed(_)));
+    }
+
+    #[test]
+    fn urls_are_checked_against_scheme_and_allowed_domains() {
+        let fetcher = AvatarFetcher::new(Arc::new(["example.com".to_string()]), 0, 0);
+        assert!(fetcher.validate_url("https://example.com/a.png").is_ok());
+        assert!(fetcher.validate_url("https://cdn.Example.com/a.png").is_ok());
+        assert!(matches!(fetcher.validate_url("https://badexample.com/"), Err(AvatarFetcherError::DomainNotAllowed(_))));
+        assert!(matches!(fetcher.validate_url("file:///etc/passwd"), Err(AvatarFetcherError::UnsupportedScheme(_))));
+        assert!(matches!(fetcher.validate_url("not a url"), Err(AvatarFetcherError::InvalidUrl)));
+    }
+}
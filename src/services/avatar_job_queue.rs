@@ -0,0 +1,133 @@
+use std::{sync::{Arc, Weak}, time::Duration};
+use tokio::sync::Notify;
+use crate::{app_state::AppState, db::{avatar_jobs::{self, AvatarJob}, DatabasePool}};
+
+/// Processes avatar jobs stored in the database with a pool of background workers.
+/// Failed jobs are retried with exponential backoff until they run out of attempts,
+/// jobs whose worker stopped responding are taken over once they become stale
+#[derive(Debug, Clone)]
+pub(crate) struct AvatarJobQueue {
+    connection_pool: DatabasePool,
+    notify: Arc<Notify>,
+    workers: usize,
+    max_attempts: i64,
+    retry_delay: Duration,
+    job_timeout: Duration,
+}
+
+impl AvatarJobQueue {
+    /// Idle workers check for jobs enqueued without a notification at least this often
+    const MAX_IDLE_WAIT: Duration = Duration::from_secs(60);
+    /// Workers wait this long after a database error
+    const ERROR_DELAY: Duration = Duration::from_secs(1);
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[inline]
+    pub(crate) fn new(
+        connection_pool: DatabasePool,
+        workers: usize,
+        max_attempts: i64,
+        retry_delay: Duration,
+        job_timeout: Duration,
+    ) -> Self {
+        Self {
+            connection_pool,
+            notify: Arc::new(Notify::new()),
+            workers,
+            max_attempts,
+            retry_delay,
+            job_timeout,
+        }
+    }
+
+    /// Wakes up a worker after a job has been enqueued
+    #[inline]
+    pub(crate) fn notify(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Spawns the workers, jobs interrupted by the previous shutdown are taken over once they become stale
+    /// as other instances sharing the database may still be working on them
+    pub(crate) fn start(&self, app_state: Weak<AppState>) {
+        for _ in 0..self.workers {
+            let queue = self.clone();
+            let app_state = app_state.clone();
+            tokio::spawn(async move { queue.run_worker(app_state).await });
+        }
+    }
+
+    /// Running jobs claimed longer ago are assumed to have lost their worker, attempts are cancelled
+    /// after `job_timeout` so this leaves the worker enough time to record the outcome
+    #[inline]
+    fn get_stale_after(&self) -> Duration {
+        self.job_timeout.saturating_mul(2)
+    }
+
+    async fn run_worker(&self, app_state: Weak<AppState>) {
+        loop {
+            match avatar_jobs::claim_avatar_job(&self.connection_pool, self.get_stale_after()).await {
+                Ok(Some(job)) => {
+                    // The application is shutting down
+                    let Some(app_state) = app_state.upgrade() else {
+                        return;
+                    };
+                    self.process_job(&app_state, &job).await;
+                },
+                Ok(None) => self.wait_for_job().await,
+                Err(err) => {
+                    tracing::error!("Failed to claim avatar job: {}", err);
+                    tokio::time::sleep(Self::ERROR_DELAY).await;
+                },
+            }
+        }
+    }
+
+    /// Sleeps until a job is enqueued or the next job becomes due, only reading the database
+    /// so idle workers do not keep taking the write lock
+    async fn wait_for_job(&self) {
+        let wait = match avatar_jobs::get_next_avatar_job_due_date(&self.connection_pool, self.get_stale_after()).await {
+            Ok(Some(due_date)) => (due_date - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO).min(Self::MAX_IDLE_WAIT),
+            Ok(None) => Self::MAX_IDLE_WAIT,
+            Err(err) => {
+                tracing::error!("Failed to get the next avatar job: {}", err);
+                Self::ERROR_DELAY
+            },
+        };
+        if !wait.is_zero() {
+            let _ = tokio::time::timeout(wait, self.notify.notified()).await;
+        }
+    }
+
+    /// Delay before the next attempt, doubled after every failed attempt up to a day
+    #[inline]
+    fn get_retry_delay(&self, attempts: i64) -> Duration {
+        let exponent = u32::try_from(attempts.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.retry_delay
+            .saturating_mul(2u32.checked_pow(exponent).unwrap_or(u32::MAX))
+            .min(Self::MAX_RETRY_DELAY)
+    }
+
+    async fn process_job(&self, app_state: &AppState, job: &AvatarJob) {
+        let result = tokio::time::timeout(self.job_timeout, app_state.blog_post_service.attach_user_avatar(job))
+            .await
+            .unwrap_or_else(|_| Err(tokio::io::Error::from(tokio::io::ErrorKind::TimedOut).into()));
+        let Err(err) = result else {
+            tracing::debug!("Attached user avatar to post {}", job.get_post_id());
+            return;
+        };
+        let result = if err.is_transient() && job.get_attempts() < self.max_attempts {
+            let delay = self.get_retry_delay(job.get_attempts());
+            tracing::warn!(
+                "Failed to fetch user avatar of post {} (attempt {}), retrying in {:?}: {}",
+                job.get_post_id(), job.get_attempts(), delay, err,
+            );
+            avatar_jobs::retry_avatar_job(&self.connection_pool, job, delay, &err.to_string()).await
+        } else {
+            tracing::warn!("Failed to fetch user avatar of post {}: {}", job.get_post_id(), err);
+            avatar_jobs::fail_avatar_job(&self.connection_pool, job, &err.to_string()).await
+        };
+        if let Err(err) = result {
+            tracing::error!("Failed to update avatar job {}: {}", job.get_id(), err);
+        }
+    }
+}
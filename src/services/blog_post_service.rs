@@ -1,11 +1,18 @@
-use std::sync::Weak;
+use std::{convert::Infallible, net::IpAddr, sync::{Arc, Weak}, time::Duration};
+use axum::body::Bytes;
+use futures::future::OptionFuture;
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
-use crate::{app_state::AppState, db::{blog_posts, DatabasePool}, endpoints::models::get_posts_response::GetPostsResponse};
-use super::file_handler_service::FileHandle;
+use crate::{app_state::AppState, db::{avatar_jobs::{self, AvatarJob}, blog_posts, remote_images, tags, DatabaseConnection, DatabasePool}, endpoints::{escape_html, models::{get_posts_response::GetPostsResponse, search_posts_response::SearchPostsResponse}}};
+use super::{avatar_fetcher::{AvatarFetcher, AvatarFetcherError}, file_handler_service::{FileHandle, FileHandleSaveError, FileHandlerServiceError}, identicon, storage::StorageError, image_dimensions::ImageHeaderError, image_format::ImageFormat, png_validator::PngValidationError};
 
 pub(crate) struct BlogPostService {
     connection_pool: DatabasePool,
     app_state: Mutex<Weak<AppState>>,
+    max_reply_depth: i64,
+    avatar_fetcher: AvatarFetcher,
+    // Cached remote images younger than this are reused without asking the remote whether they changed
+    remote_image_ttl: Duration,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -14,107 +21,582 @@ pub(crate) enum AddingBlogPostError {
     ReqwestError(#[from] reqwest::Error),
     #[error("Failed to access database: {0}")]
     SqlxError(#[from] sqlx::Error),
-    #[error("User avatar is not an png image")]
-    UserAvatarIsNotAnPNGImage,
+    #[error("User avatar is not an image in a supported format, accepted formats are: {}", ImageFormat::supported_formats())]
+    UserAvatarHasUnsupportedFormat,
     #[error("Failed to fetch user avatar")]
     FailedToFetchUserAvatar,
+    #[error("User avatar url does not lead to an existing image")]
+    UserAvatarNotFound,
+    #[error("User email is not valid")]
+    UserEmailIsInvalid,
+    #[error("Failed to render identicon: {0}")]
+    IdenticonRenderingFailed(#[source] image::ImageError),
+    #[error("User avatar url is not valid")]
+    UserAvatarUrlIsInvalid,
+    #[error("User avatar url has unsupported scheme {0}, only http and https are allowed")]
+    UserAvatarUrlHasUnsupportedScheme(String),
+    #[error("User avatars cannot be fetched from {0}")]
+    UserAvatarDomainIsNotAllowed(String),
+    #[error("User avatar host {0} cannot be resolved")]
+    UserAvatarHostCannotBeResolved(String),
+    // The address is not shown to the user so the layout of internal networks is not revealed
+    #[error("User avatar url leads to an address which is not publicly reachable")]
+    UserAvatarAddressIsNotAllowed(IpAddr),
+    #[error("User avatar url redirects too many times")]
+    TooManyUserAvatarRedirects,
     #[error("Tokio IO error: {0}")]
     TokioIoError(#[from] tokio::io::Error),
+    #[error("Failed to access image storage: {0}")]
+    StorageFailed(#[from] StorageError),
     #[error("User avatar is too big")]
     UserAvatarIsTooBig,
+    #[error("User avatar is not a valid PNG image: {0}")]
+    UserAvatarIsInvalidPng(#[source] PngValidationError),
+    #[error("User avatar is not a valid image: {0}")]
+    UserAvatarHasInvalidHeader(#[source] ImageHeaderError),
+    #[error("User avatar dimensions {width}x{height} exceed the allowed limits")]
+    UserAvatarDimensionsTooBig { width: u32, height: u32 },
+    #[error("Replied post does not exist")]
+    ParentPostNotFound,
+    #[error("Replies cannot be nested deeper than {0} levels")]
+    ReplyNestingTooDeep(i64),
+    #[error("Invalid tag \"{0}\", tags can contain only letters, digits, '_' and '-' and have at most 64 characters")]
+    InvalidTag(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum UpdatingBlogPostError {
+    #[error("Post not found")]
+    PostNotFound,
+    #[error("{0}")]
+    AddingBlogPostError(#[from] AddingBlogPostError),
+}
+
+impl AddingBlogPostError {
+    /// Whether trying again later may succeed, as opposed to errors caused by what the user submitted
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::ReqwestError(_)
+                | Self::SqlxError(_)
+                | Self::FailedToFetchUserAvatar
+                | Self::UserAvatarHostCannotBeResolved(_)
+                | Self::TokioIoError(_)
+                | Self::StorageFailed(_)
+        )
+    }
+}
+
+impl From<sqlx::Error> for UpdatingBlogPostError {
+    #[inline]
+    fn from(err: sqlx::Error) -> Self {
+        Self::AddingBlogPostError(err.into())
+    }
+}
+
+/// Describes what should happen with an image attached to an edited post
+#[derive(Debug)]
+pub(crate) enum ImageChange<T> {
+    Keep,
+    Remove,
+    Replace(T),
+}
+
+/// Who wrote a new post and how their avatar should be obtained
+#[derive(Debug)]
+pub(crate) struct PostAuthor {
+    pub user_name: String,
+    /// Avatar fetched from this url takes precedence over the Gravatar of the email
+    pub user_avatar_url: Option<String>,
+    /// Only its hash is stored
+    pub user_email: Option<String>,
+}
+
+/// Avatar of a post fetched from a url
+#[derive(Debug)]
+enum UserAvatar {
+    /// Image fetched from the same url before which is still fresh or has not changed
+    Cached(i64),
+    /// Newly downloaded image, it is cached under the url once its row is inserted
+    Fetched {
+        image: Box<FileHandle>,
+        source_url: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+impl UserAvatar {
+    /// Inserts the image row (if there is a new image) and returns the id of the image
+    async fn insert(&mut self, connection: &mut DatabaseConnection) -> Result<Option<i64>, AddingBlogPostError> {
+        let UserAvatar::Fetched { image, source_url, etag, last_modified } = self else {
+            return Ok(self.get_id());
+        };
+        image.insert(connection).await.map_err(BlogPostService::map_save_error)?;
+        if let Some(id) = image.get_id() {
+            remote_images::upsert_remote_image(connection, source_url, id, etag.as_deref(), last_modified.as_deref()).await?;
+        }
+        Ok(image.get_id())
+    }
+
+    /// Has to be called after the row inserted by `insert` has been committed
+    async fn promote(&mut self) -> Result<(), FileHandleSaveError> {
+        match self {
+            UserAvatar::Cached(_) => Ok(()),
+            UserAvatar::Fetched { image, .. } => image.promote().await,
+        }
+    }
+
+    #[inline]
+    fn get_id(&self) -> Option<i64> {
+        match self {
+            UserAvatar::Cached(id) => Some(*id),
+            UserAvatar::Fetched { image, .. } => image.get_id(),
+        }
+    }
+}
+
+impl ImageChange<FileHandle> {
+    /// Inserts the row of a replacement image and returns the id the post should reference afterwards
+    async fn insert(&mut self, connection: &mut DatabaseConnection, current: Option<i64>) -> Result<Option<i64>, AddingBlogPostError> {
+        match self {
+            ImageChange::Keep => Ok(current),
+            ImageChange::Remove => Ok(None),
+            ImageChange::Replace(file_handle) => {
+                file_handle.insert(connection).await.map_err(BlogPostService::map_save_error)?;
+                Ok(file_handle.get_id())
+            },
+        }
+    }
+
+    /// Has to be called after the row inserted by `insert` has been committed
+    async fn promote(&mut self) -> Result<(), FileHandleSaveError> {
+        match self {
+            ImageChange::Keep | ImageChange::Remove => Ok(()),
+            ImageChange::Replace(file_handle) => file_handle.promote().await,
+        }
+    }
 }
 
 impl BlogPostService {
+    const GRAVATAR_URL: &'static str = "https://gravatar.com/avatar/";
+    const GRAVATAR_SIZE: u32 = 256;
+
     #[inline]
-    pub(crate) fn new(connection_pool: DatabasePool) -> Self {
+    pub(crate) fn new(
+        connection_pool: DatabasePool,
+        max_reply_depth: i64,
+        avatar_fetcher: AvatarFetcher,
+        remote_image_ttl: Duration,
+    ) -> Self {
         Self {
             connection_pool,
             app_state: Mutex::new(Weak::new()),
+            max_reply_depth,
+            avatar_fetcher,
+            remote_image_ttl,
+        }
+    }
+
+    fn map_avatar_fetcher_error(err: AvatarFetcherError) -> AddingBlogPostError {
+        match err {
+            AvatarFetcherError::ReqwestError(err) => err.into(),
+            AvatarFetcherError::InvalidUrl => AddingBlogPostError::UserAvatarUrlIsInvalid,
+            AvatarFetcherError::UnsupportedScheme(scheme) => AddingBlogPostError::UserAvatarUrlHasUnsupportedScheme(scheme),
+            AvatarFetcherError::DomainNotAllowed(domain) => AddingBlogPostError::UserAvatarDomainIsNotAllowed(domain),
+            AvatarFetcherError::HostNotResolved(host) => AddingBlogPostError::UserAvatarHostCannotBeResolved(host),
+            AvatarFetcherError::AddressNotAllowed(address) => AddingBlogPostError::UserAvatarAddressIsNotAllowed(address),
+            AvatarFetcherError::TooManyRedirects => AddingBlogPostError::TooManyUserAvatarRedirects,
+            AvatarFetcherError::TooBig(_) => AddingBlogPostError::UserAvatarIsTooBig,
+            // Missing images are not retried, e.g. Gravatar responds so when there is no avatar for the email and the identicon is kept
+            AvatarFetcherError::UnsuccessfulStatus(reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE) =>
+                AddingBlogPostError::UserAvatarNotFound,
+            AvatarFetcherError::UnsuccessfulStatus(_) => AddingBlogPostError::FailedToFetchUserAvatar,
+        }
+    }
+
+    #[inline]
+    fn get_header(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+        response.headers().get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    }
+
+    /// Reuses the image fetched from the same url before if it is fresh or the remote confirms it has not changed
+    async fn fetch_user_avatar(&self, user_avatar_url: &str) -> Result<UserAvatar, AddingBlogPostError> {
+        let app_state = self.get_app_state().await;
+        let cached_image = remote_images::get_remote_image(&self.connection_pool, user_avatar_url).await?;
+        if let Some(cached_image) = cached_image.as_ref() {
+            let age = (chrono::Utc::now() - cached_image.get_fetched_at()).to_std().unwrap_or_default();
+            if age < self.remote_image_ttl {
+                return Ok(UserAvatar::Cached(cached_image.get_image_id()));
+            }
+        }
+        let response = self.avatar_fetcher.fetch(
+            &app_state.http_client,
+            user_avatar_url,
+            cached_image.as_ref().and_then(|v| v.get_etag()),
+            cached_image.as_ref().and_then(|v| v.get_last_modified()),
+        ).await.map_err(Self::map_avatar_fetcher_error)?;
+        let etag = Self::get_header(&response, reqwest::header::ETAG);
+        let last_modified = Self::get_header(&response, reqwest::header::LAST_MODIFIED);
+        if let (reqwest::StatusCode::NOT_MODIFIED, Some(cached_image)) = (response.status(), cached_image) {
+            remote_images::refresh_remote_image(
+                &self.connection_pool, user_avatar_url, etag.as_deref(), last_modified.as_deref(),
+            ).await?;
+            return Ok(UserAvatar::Cached(cached_image.get_image_id()));
+        }
+
+        // Some images does not have Content-Type header or even though they are PNG images, they are not marked as such
+        // let is_image= response.headers()
+        //     .get("Content-Type")
+        //     .and_then(|v| v.to_str().ok())
+        //     .map(|v| v == "image/png")
+        //     .unwrap_or(false);
+        // if !is_image {
+        //     return Err(AddingBlogPostError::UserAvatarHasUnsupportedFormat);
+        // }
+
+        let image = app_state.file_handler_service
+            .save_file_with_max_size(response.bytes_stream(), self.avatar_fetcher.get_max_avatar_size()).await
+            .map_err(Self::map_file_handler_error)?;
+        Ok(UserAvatar::Fetched { image: Box::new(image), source_url: user_avatar_url.to_string(), etag, last_modified })
+    }
+
+    fn map_file_handler_error(err: FileHandlerServiceError) -> AddingBlogPostError {
+        match err {
+            FileHandlerServiceError::TokioIoError(err) => err.into(),
+            FileHandlerServiceError::SqlxError(err) => err.into(),
+            FileHandlerServiceError::StorageFailed(err) => err.into(),
+            FileHandlerServiceError::UnsupportedFileFormat => AddingBlogPostError::UserAvatarHasUnsupportedFormat,
+            FileHandlerServiceError::FileIsTooBig => AddingBlogPostError::UserAvatarIsTooBig,
+            FileHandlerServiceError::InvalidPngImage(err) => AddingBlogPostError::UserAvatarIsInvalidPng(err),
+            FileHandlerServiceError::InvalidImageHeader(err) => AddingBlogPostError::UserAvatarHasInvalidHeader(err),
+            FileHandlerServiceError::ImageDimensionsTooBig { width, height } =>
+                AddingBlogPostError::UserAvatarDimensionsTooBig { width, height },
+        }
+    }
+
+    /// Stages the identicon of the user, it is the avatar of every post until (and unless) a fetched avatar replaces it
+    async fn create_identicon(&self, user_name: &str) -> Result<FileHandle, AddingBlogPostError> {
+        let content = identicon::render_identicon(user_name).map_err(AddingBlogPostError::IdenticonRenderingFailed)?;
+        self.get_app_state().await.file_handler_service
+            .save_file(futures::stream::iter([Ok::<_, Infallible>(Bytes::from(content))])).await
+            .map_err(Self::map_file_handler_error)
+    }
+
+    /// Hash of the normalized email in the form Gravatar identifies avatars by
+    fn hash_user_email(user_email: Option<String>) -> Result<Option<String>, AddingBlogPostError> {
+        let Some(user_email) = user_email.map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty()) else {
+            return Ok(None);
+        };
+        match user_email.split_once('@') {
+            Some((local, domain)) if !local.is_empty() && !domain.is_empty() && !user_email.contains(char::is_whitespace) =>
+                Ok(Some(Sha256::digest(user_email.as_bytes()).iter().map(|v| format!("{:02x}", v)).collect())),
+            _ => Err(AddingBlogPostError::UserEmailIsInvalid),
+        }
+    }
+
+    /// Gravatar url of the email, `None` if avatars cannot be fetched from Gravatar
+    fn get_gravatar_url(&self, user_email_hash: &str) -> Option<String> {
+        let url = format!("{}{}?s={}&d=404", Self::GRAVATAR_URL, user_email_hash, Self::GRAVATAR_SIZE);
+        self.avatar_fetcher.validate_url(&url).ok().map(|_| url)
+    }
+
+    #[inline]
+    fn trim_user_avatar_url(user_avatar_url: Option<String>) -> Option<String> {
+        user_avatar_url
+            .map(|v| v.trim().to_string())
+            .and_then(|v| if v.is_empty() { None } else { Some(v) })
+    }
+
+    fn map_save_error(err: FileHandleSaveError) -> AddingBlogPostError {
+        match err {
+            FileHandleSaveError::SqlxError(err) => err.into(),
+            FileHandleSaveError::MovingFileFailed(err) => err.into(),
+        }
+    }
+
+    const MAX_TAG_LENGTH: usize = 64;
+
+    #[inline]
+    fn is_tag_character(c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || c == '-'
+    }
+
+    /// Strips an optional leading `#` and lowercases the tag, returns `None` if the tag is not valid
+    fn normalize_tag(tag: &str) -> Option<String> {
+        let tag = tag.strip_prefix('#').unwrap_or(tag);
+        match !tag.is_empty() && tag.chars().count() <= Self::MAX_TAG_LENGTH && tag.chars().all(Self::is_tag_character) {
+            true => Some(tag.to_lowercase()),
+            false => None,
+        }
+    }
+
+    /// Finds all `#hashtags` in the content, a `#` preceded by a letter, digit, `/` or `&` does not start a hashtag
+    /// so URL fragments and HTML entities are skipped
+    fn extract_hashtags(content: &str) -> Vec<String> {
+        let mut hashtags = std::collections::BTreeSet::new();
+        let mut previous = None;
+        for (index, c) in content.char_indices() {
+            let starts_hashtag = c == '#' && !previous.is_some_and(|p: char| Self::is_tag_character(p) || "#/&".contains(p));
+            previous = Some(c);
+            if !starts_hashtag {
+                continue;
+            }
+            let rest = &content[index + 1..];
+            let end = rest.find(|c| !Self::is_tag_character(c)).unwrap_or(rest.len());
+            if let Some(tag) = Self::normalize_tag(rest[..end].trim_end_matches('-')) {
+                hashtags.insert(tag);
+            }
         }
+        hashtags.into_iter().collect()
     }
 
+    #[inline]
     pub(crate) async fn add_post(
         &self, 
-        user_name: String,
+        author: PostAuthor,
+        content: String,
+        post_image: Option<FileHandle>,
+        tags: Vec<String>,
+    ) -> Result<(), AddingBlogPostError> {
+        self.insert_post(author, content, post_image, tags, None).await
+    }
+
+    pub(crate) async fn add_reply(
+        &self,
+        parent_id: i64,
+        author: PostAuthor,
+        content: String,
+        post_image: Option<FileHandle>,
+        tags: Vec<String>,
+    ) -> Result<(), AddingBlogPostError> {
+        let depth = blog_posts::get_reply_depth(&self.connection_pool, parent_id).await?
+            .ok_or(AddingBlogPostError::ParentPostNotFound)?;
+        if depth > self.max_reply_depth {
+            return Err(AddingBlogPostError::ReplyNestingTooDeep(self.max_reply_depth));
+        }
+        self.insert_post(author, content, post_image, tags, Some(parent_id)).await
+    }
+
+    async fn insert_post(
+        &self,
+        author: PostAuthor,
         content: String,
-        mut user_avatar_url: Option<String>,
         mut post_image: Option<FileHandle>,
+        tags: Vec<String>,
+        parent_id: Option<i64>,
     ) -> Result<(), AddingBlogPostError> {
-        let mut user_avatar = None;
-        user_avatar_url = user_avatar_url.take()
-            .map(|v| v.trim().to_string())
-            .and_then(|v| if v.is_empty() { None } else { Some(v) });
-        if let Some(user_avatar_url) = user_avatar_url.as_mut() {
-            let response = reqwest::get(user_avatar_url.as_str()).await?;
-            if !response.status().is_success() {
-                return Err(AddingBlogPostError::FailedToFetchUserAvatar);
-            }
+        let explicit_tags = tags.iter()
+            .map(|tag| Self::normalize_tag(tag).ok_or_else(|| AddingBlogPostError::InvalidTag(tag.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let PostAuthor { user_name, user_avatar_url, user_email } = author;
+        let user_avatar_url = Self::trim_user_avatar_url(user_avatar_url);
+        if let Some(user_avatar_url) = user_avatar_url.as_deref() {
+            self.avatar_fetcher.validate_url(user_avatar_url).map_err(Self::map_avatar_fetcher_error)?;
+        }
+        let user_email_hash = Self::hash_user_email(user_email)?;
+        let is_fallback_avatar = user_avatar_url.is_none();
+        let user_avatar_url = user_avatar_url
+            .or_else(|| user_email_hash.as_deref().and_then(|v| self.get_gravatar_url(v)));
+        let mut identicon = self.create_identicon(&user_name).await?;
 
-            // Some images does not have Content-Type header or even though they are PNG images, they are not marked as such
-            // let is_image= response.headers()
-            //     .get("Content-Type")
-            //     .and_then(|v| v.to_str().ok())
-            //     .map(|v| v == "image/png")
-            //     .unwrap_or(false);
-            // if !is_image {
-            //     return Err(AddingBlogPostError::UserAvatarIsNotAnPNGImage);
-            // }
-            
-            let user_avatar_tmp = self.app_state.lock().await.upgrade()
-                .expect("Service do not have a valid reference to app state")
-                .file_handler_service
-                .save_file(response.bytes_stream()).await.map_err(|err| {
-                    use super::file_handler_service::FileHandlerServiceError;
-                    match err {
-                        FileHandlerServiceError::TokioIoError(err) => err.into(),
-                        FileHandlerServiceError::SqlxError(err) => err.into(),
-                        FileHandlerServiceError::FileIsNotAnPNGImage => AddingBlogPostError::UserAvatarIsNotAnPNGImage,
-                        FileHandlerServiceError::FileIsTooBig => AddingBlogPostError::UserAvatarIsTooBig,
-                    }
-                })?;
-            *user_avatar_url = user_avatar_tmp.get_name()
-                .and_then(|v| v.to_str())
-                .map(|v| v.to_string())
-                .ok_or(AddingBlogPostError::TokioIoError(
-                    tokio::io::Error::new(tokio::io::ErrorKind::InvalidData, "Failed to parse file name")))?;
-            user_avatar = Some(user_avatar_tmp);
-        }
-
-        for image in [post_image.as_mut(), user_avatar.as_mut()].into_iter().flatten() {
-            image.save().await.map_err(|err| {
-                use super::file_handler_service::FileHandleSaveError;
-                use tokio::io::{Error, ErrorKind};
-                match err {
-                    FileHandleSaveError::SqlxError(err) => err.into(),
-                    FileHandleSaveError::FileNameParsingError => AddingBlogPostError::TokioIoError(
-                        Error::new(ErrorKind::InvalidData, "Failed to parse file name")),
-            }})?;
-        }
-        blog_posts::insert_post(
-            &self.connection_pool,
+        // Files are moved out of the staging directory only once the post has been committed,
+        // dropping the handles on failure removes them
+        let mut transaction = self.connection_pool.begin().await?;
+        if let Some(post_image) = post_image.as_mut() {
+            post_image.insert(&mut transaction).await.map_err(Self::map_save_error)?;
+        }
+        identicon.insert(&mut transaction).await.map_err(Self::map_save_error)?;
+        let id = blog_posts::insert_post(
+            &mut transaction,
             &user_name,
             &content,
-            user_avatar.and_then(|v| v.get_id()),
-            post_image.and_then(|v| v.get_id()),
+            user_email_hash.as_deref(),
+            identicon.get_id(),
+            post_image.as_ref().and_then(|v| v.get_id()),
+            parent_id,
         ).await?;
+        tags::insert_post_tags(&mut transaction, id, &explicit_tags, true).await?;
+        tags::insert_post_tags(&mut transaction, id, &Self::extract_hashtags(&content), false).await?;
+        // The avatar is fetched in the background and attached once it is ready
+        if let Some(user_avatar_url) = user_avatar_url.as_deref() {
+            avatar_jobs::insert_avatar_job(&mut transaction, id, user_avatar_url, is_fallback_avatar).await?;
+        }
+        transaction.commit().await?;
+        if user_avatar_url.is_some() {
+            self.get_app_state().await.avatar_job_queue.notify();
+        }
+        // The post already exists, an image whose file could not be stored is reported by the image garbage collector
+        // and is healed by uploading the same image again
+        if let Some(Err(err)) = OptionFuture::from(post_image.as_mut().map(|v| v.promote())).await {
+            tracing::error!("Failed to promote image of post {}: {}", id, err);
+        }
+        if let Err(err) = identicon.promote().await {
+            tracing::error!("Failed to promote identicon of post {}: {}", id, err);
+        }
         Ok(())
     }
 
+    pub(crate) async fn update_post(
+        &self,
+        id: i64,
+        content: Option<String>,
+        user_avatar_url: ImageChange<String>,
+        mut post_image: ImageChange<FileHandle>,
+    ) -> Result<(), UpdatingBlogPostError> {
+        let old_images = blog_posts::get_post_images(&self.connection_pool, id).await?
+            .ok_or(UpdatingBlogPostError::PostNotFound)?;
+        let user_avatar_url = match user_avatar_url {
+            ImageChange::Replace(user_avatar_url) => match Self::trim_user_avatar_url(Some(user_avatar_url)) {
+                Some(user_avatar_url) => {
+                    self.avatar_fetcher.validate_url(&user_avatar_url).map_err(Self::map_avatar_fetcher_error)?;
+                    ImageChange::Replace(user_avatar_url)
+                },
+                None => ImageChange::Remove,
+            },
+            ImageChange::Keep => ImageChange::Keep,
+            ImageChange::Remove => ImageChange::Remove,
+        };
+        // A replaced avatar is kept until the new one has been fetched, a removed one falls back to the identicon
+        let mut identicon = match user_avatar_url {
+            ImageChange::Keep | ImageChange::Replace(_) => None,
+            ImageChange::Remove => {
+                let user_name = blog_posts::get_post(&self.connection_pool, id).await?
+                    .ok_or(UpdatingBlogPostError::PostNotFound)?
+                    .user_name;
+                Some(self.create_identicon(&user_name).await?)
+            },
+        };
+
+        // As in `insert_post` files are moved out of the staging directory only once the update has been committed
+        let mut transaction = self.connection_pool.begin().await?;
+        let new_post_image = post_image.insert(&mut transaction, old_images.post_image).await?;
+        let new_user_avatar = match identicon.as_mut() {
+            Some(identicon) => {
+                identicon.insert(&mut transaction).await.map_err(Self::map_save_error)?;
+                identicon.get_id()
+            },
+            None => old_images.user_avatar,
+        };
+        if !blog_posts::update_post(
+            &mut transaction,
+            id,
+            content.as_deref(),
+            new_user_avatar,
+            new_post_image,
+        ).await? {
+            return Err(UpdatingBlogPostError::PostNotFound);
+        }
+        match user_avatar_url {
+            ImageChange::Keep => (),
+            ImageChange::Remove => avatar_jobs::cancel_avatar_jobs(&mut transaction, id).await?,
+            ImageChange::Replace(ref user_avatar_url) =>
+                avatar_jobs::insert_avatar_job(&mut transaction, id, user_avatar_url, false).await?,
+        }
+        if let Some(content) = content {
+            tags::delete_extracted_post_tags(&mut transaction, id).await?;
+            tags::insert_post_tags(&mut transaction, id, &Self::extract_hashtags(&content), false).await?;
+        }
+        transaction.commit().await?;
+        if let ImageChange::Replace(_) = user_avatar_url {
+            self.get_app_state().await.avatar_job_queue.notify();
+        }
+        if let Err(err) = post_image.promote().await {
+            tracing::error!("Failed to promote image of post {}: {}", id, err);
+        }
+        if let Some(Err(err)) = OptionFuture::from(identicon.as_mut().map(|v| v.promote())).await {
+            tracing::error!("Failed to promote identicon of post {}: {}", id, err);
+        }
+        for (old, new) in [(old_images.user_avatar, new_user_avatar), (old_images.post_image, new_post_image)] {
+            if old != new {
+                self.release_image(old).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the avatar of the job and attaches it to the post, nothing is attached if the job has been cancelled
+    /// or replaced by another one in the meantime
+    pub(crate) async fn attach_user_avatar(&self, job: &AvatarJob) -> Result<(), AddingBlogPostError> {
+        let user_avatar = match self.fetch_user_avatar(job.get_source_url()).await {
+            Ok(user_avatar) => Some(user_avatar),
+            // Most emails have no Gravatar, the post keeps its identicon then
+            Err(AddingBlogPostError::UserAvatarNotFound) if job.is_fallback() => None,
+            Err(err) => return Err(err),
+        };
+        let mut transaction = self.connection_pool.begin().await?;
+        if !avatar_jobs::delete_avatar_job(&mut transaction, job.get_id()).await? {
+            return Ok(());
+        }
+        let Some(mut user_avatar) = user_avatar else {
+            avatar_jobs::cancel_avatar_jobs(&mut transaction, job.get_post_id()).await?;
+            return Ok(transaction.commit().await?);
+        };
+        let user_avatar_id = user_avatar.insert(&mut transaction).await?;
+        let Some(old_user_avatar) = blog_posts::set_user_avatar(&mut transaction, job.get_post_id(), user_avatar_id).await? else {
+            return Ok(());
+        };
+        transaction.commit().await?;
+        if let Err(err) = user_avatar.promote().await {
+            tracing::error!("Failed to promote user avatar of post {}: {}", job.get_post_id(), err);
+        }
+        if old_user_avatar != user_avatar_id {
+            self.release_image(old_user_avatar).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the post and all replies beneath it
+    pub(crate) async fn delete_post(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let deleted_posts = blog_posts::delete_post(&self.connection_pool, id).await?;
+        for images in deleted_posts.iter() {
+            self.release_image(images.user_avatar).await?;
+            self.release_image(images.post_image).await?;
+        }
+        Ok(!deleted_posts.is_empty())
+    }
+
+    #[inline]
+    async fn release_image(&self, id: Option<i64>) -> Result<(), sqlx::Error> {
+        match id {
+            Some(id) => self.get_app_state().await.file_handler_service.release_image(id).await,
+            None => Ok(()),
+        }
+    }
+
+    #[inline]
+    async fn get_app_state(&self) -> Arc<AppState> {
+        self.app_state.lock().await.upgrade()
+            .expect("Service do not have a valid reference to app state")
+    }
+
     #[inline]
     pub(crate) async fn set_app_state(&self, app_state: Weak<AppState>) {
         *self.app_state.lock().await = app_state;
     }
     
-    pub(crate) async fn get_posts(&self, limit: Option<i64>, offset: Option<i64>) -> Result<GetPostsResponse, sqlx::Error> {
-    let limit = limit.map(|v| v.clamp(1, 100)).unwrap_or(10);
-        let offset = offset.map(|v| v.max(0)).unwrap_or(0);
+    #[inline]
+    fn normalize_pagination(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+        (
+            limit.map(|v| v.clamp(1, 100)).unwrap_or(10),
+            offset.map(|v| v.max(0)).unwrap_or(0),
+        )
+    }
+
+    /// When `tag` is given only posts with that tag are returned
+    pub(crate) async fn get_posts(&self, limit: Option<i64>, offset: Option<i64>, tag: Option<&str>) -> Result<GetPostsResponse, sqlx::Error> {
+        let tag = tag.map(|tag| Self::normalize_tag(tag).unwrap_or_default());
+        let (limit, offset) = Self::normalize_pagination(limit, offset);
         Ok(
             GetPostsResponse {
                 limit,
                 offset,
-                total: blog_posts::get_total_amount_of_posts(&self.connection_pool).await?,
+                total: blog_posts::get_total_amount_of_posts(&self.connection_pool, tag.as_deref()).await?,
                 posts: blog_posts::get_newest_posts(
                     &self.connection_pool,
+                    tag.as_deref(),
                     limit,
                     offset,
                 ).await?,
@@ -122,6 +604,76 @@ impl BlogPostService {
         )
     }
     
+    /// Returns `None` if the replied post does not exist
+    pub(crate) async fn get_replies(&self, parent_id: i64, limit: Option<i64>, offset: Option<i64>) -> Result<Option<GetPostsResponse>, sqlx::Error> {
+        if !blog_posts::post_exists(&self.connection_pool, parent_id).await? {
+            return Ok(None);
+        }
+        let (limit, offset) = Self::normalize_pagination(limit, offset);
+        Ok(Some(
+            GetPostsResponse {
+                limit,
+                offset,
+                total: blog_posts::get_total_amount_of_replies(&self.connection_pool, parent_id).await?,
+                posts: blog_posts::get_replies(
+                    &self.connection_pool,
+                    parent_id,
+                    limit,
+                    offset,
+                ).await?,
+            }
+        ))
+    }
+
+    /// Turns user input into an FTS5 query matching posts containing all of the given words.
+    /// Every word is quoted so characters with special meaning in FTS5 syntax are matched literally
+    fn create_search_query(query: &str) -> Option<String> {
+        let terms = query.split_whitespace()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>();
+        match terms.is_empty() {
+            true => None,
+            false => Some(terms.join(" ")),
+        }
+    }
+
+    /// Escapes the snippet and wraps matched terms in `<mark>` tags
+    fn highlight_snippet(snippet: &str) -> String {
+        escape_html(snippet)
+            .replace(blog_posts::SNIPPET_MATCH_START, "<mark>")
+            .replace(blog_posts::SNIPPET_MATCH_END, "</mark>")
+    }
+
+    /// Returns `None` if the query does not contain any words
+    pub(crate) async fn search_posts(&self, query: &str, limit: Option<i64>, offset: Option<i64>) -> Result<Option<SearchPostsResponse>, sqlx::Error> {
+        let Some(query) = Self::create_search_query(query) else {
+            return Ok(None);
+        };
+        let (limit, offset) = Self::normalize_pagination(limit, offset);
+        let mut results = blog_posts::search_posts(&self.connection_pool, &query, limit, offset).await?;
+        for result in results.iter_mut() {
+            result.snippet = Self::highlight_snippet(&result.snippet);
+        }
+        Ok(Some(
+            SearchPostsResponse {
+                limit,
+                offset,
+                total: blog_posts::get_total_amount_of_search_results(&self.connection_pool, &query).await?,
+                results,
+            }
+        ))
+    }
+
+    #[inline]
+    pub(crate) async fn get_post(&self, id: i64) -> Result<Option<blog_posts::Post>, sqlx::Error> {
+        blog_posts::get_post(&self.connection_pool, id).await
+    }
+
+    #[inline]
+    pub(crate) async fn get_tags(&self) -> Result<Vec<tags::TagCount>, sqlx::Error> {
+        tags::get_tags_with_counts(&self.connection_pool).await
+    }
+
     #[inline]
     pub(crate) async fn get_posts_all(&self) -> Result<Vec<blog_posts::Post>, sqlx::Error> {
         blog_posts::get_all_newest_posts(&self.connection_pool).await
@@ -0,0 +1,86 @@
+/// Requests with more ranges than this are answered with the whole file
+const MAX_RANGES: usize = 16;
+
+/// Inclusive range of bytes of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    #[inline]
+    pub(crate) fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RangeRequest {
+    /// There was no `Range` header or it has been ignored
+    Full,
+    /// Ranges are clamped to the size of the file and kept in the requested order
+    Partial(Vec<ByteRange>),
+    NotSatisfiable,
+}
+
+#[inline]
+fn parse_position(value: &str) -> Option<u64> {
+    match !value.is_empty() && value.bytes().all(|v| v.is_ascii_digit()) {
+        true => value.parse().ok(),
+        false => None,
+    }
+}
+
+/// Parses the value of a `Range` header for a file of the given size.
+/// Malformed headers, other units and requests which would read more than the whole file (e.g. many overlapping ranges)
+/// are ignored, as they are allowed to be
+pub(crate) fn parse_range_header(value: &str, size: u64) -> RangeRequest {
+    let Some(specifiers) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let mut ranges = Vec::new();
+    let mut is_empty = true;
+    for specifier in specifiers.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        is_empty = false;
+        let Some((start, end)) = specifier.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = match (start.trim(), end.trim()) {
+            ("", suffix_length) => {
+                let Some(suffix_length) = parse_position(suffix_length) else {
+                    return RangeRequest::Full;
+                };
+                (suffix_length > 0 && size > 0)
+                    .then(|| ByteRange { start: size.saturating_sub(suffix_length), end: size - 1 })
+            },
+            (start, "") => {
+                let Some(start) = parse_position(start) else {
+                    return RangeRequest::Full;
+                };
+                (start < size).then(|| ByteRange { start, end: size - 1 })
+            },
+            (start, end) => {
+                let (Some(start), Some(end)) = (parse_position(start), parse_position(end)) else {
+                    return RangeRequest::Full;
+                };
+                if end < start {
+                    return RangeRequest::Full;
+                }
+                (start < size).then(|| ByteRange { start, end: end.min(size - 1) })
+            },
+        };
+        ranges.extend(range);
+    }
+    if is_empty {
+        return RangeRequest::Full;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::NotSatisfiable;
+    }
+    let total_length = ranges.iter().map(ByteRange::len).sum::<u64>();
+    match ranges.len() > MAX_RANGES || (ranges.len() > 1 && total_length > size) {
+        true => RangeRequest::Full,
+        false => RangeRequest::Partial(ranges),
+    }
+}
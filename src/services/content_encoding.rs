@@ -0,0 +1,59 @@
+/// Encodings which static files can be served with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// In order of preference when the client accepts several of them equally
+    pub(crate) const ALL: [ContentEncoding; 2] = [Self::Brotli, Self::Gzip];
+
+    #[inline]
+    pub(crate) fn get_name(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Extension of precompressed siblings of static files, e.g. `style.css.br`
+    #[inline]
+    pub(crate) fn get_extension(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gz",
+        }
+    }
+
+    /// Returns the encodings allowed by the value of an `Accept-Encoding` header, the most preferred first
+    pub(crate) fn parse_accept_encoding(value: &str) -> Vec<Self> {
+        let mut qualities = Vec::new();
+        let mut wildcard_quality = None;
+        for item in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let mut parameters = item.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default().to_ascii_lowercase();
+            // Invalid weights are treated as the default one
+            let quality = parameters
+                .find_map(|v| v.strip_prefix("q=").or_else(|| v.strip_prefix("Q=")))
+                .and_then(|v| v.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match name.as_str() {
+                "*" => wildcard_quality = Some(quality),
+                "x-gzip" => qualities.push(("gzip".to_string(), quality)),
+                _ => qualities.push((name, quality)),
+            }
+        }
+        let mut encodings = Self::ALL.iter()
+            .filter_map(|encoding| {
+                let quality = qualities.iter()
+                    .find(|(name, _)| name == encoding.get_name())
+                    .map(|(_, quality)| *quality)
+                    .or(wildcard_quality)?;
+                (quality > 0.0).then_some((*encoding, quality))
+            })
+            .collect::<Vec<_>>();
+        encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+        encodings.into_iter().map(|(encoding, _)| encoding).collect()
+    }
+}
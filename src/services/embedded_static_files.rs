@@ -0,0 +1,9 @@
+// Generated by build.rs from the content of static_files/
+include!(concat!(env!("OUT_DIR"), "/embedded_static_files.rs"));
+
+/// Returns the content of the static file compiled into the executable
+pub(crate) fn get_embedded_static_file(file_name: &str) -> Option<&'static [u8]> {
+    EMBEDDED_STATIC_FILES.binary_search_by(|(name, _)| (*name).cmp(file_name))
+        .ok()
+        .map(|i| EMBEDDED_STATIC_FILES[i].1)
+}
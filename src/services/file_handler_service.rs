@@ -1,12 +1,11 @@
-use std::{error, path::{Path, PathBuf}};
+use std::{error, io::SeekFrom, path::{Path, PathBuf}, sync::Arc, time::{Duration, SystemTime}};
 use axum::body::Bytes;
-use futures::{pin_mut, Stream, TryStreamExt};
+use futures::{pin_mut, Stream, TryFutureExt, TryStreamExt};
 use sha2::Digest;
-use tokio::{fs::File, io::{AsyncReadExt, AsyncWriteExt}};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, Take}};
 use tokio_util::io::{ReaderStream, StreamReader};
-use crate::db::{image::{get_image_by_hash, insert_image}, DatabasePool};
-
-const PNG_HEADER: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+use crate::db::{image::{self, get_image_by_filename, get_image_by_hash, get_image_variant, insert_image, Image, ImageVariant}, DatabaseConnection, DatabasePool};
+use super::{byte_range::ByteRange, storage::{ByteStream, StorageBackend, StorageError, StoredObject}, image_variants::generate_variants, image_dimensions::{ImageDimensionsParser, ImageHeaderError}, image_format::ImageFormat, metadata_stripper::MetadataStripper, png_validator::{PngValidationError, PngValidator}};
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum GetFileFromDirectoryError {
@@ -19,15 +18,15 @@ pub(crate) enum GetFileFromDirectoryError {
 }
 
 // It is expected that folder_path is canonicalized
-pub(super) async fn get_file_from_directory(
-    folder_path: PathBuf,
-    filename: &str
-) -> Result<ReaderStream<File>, GetFileFromDirectoryError> {
+async fn resolve_path_in_directory(folder_path: PathBuf, filename: &str) -> Result<PathBuf, GetFileFromDirectoryError> {
     let mut path = folder_path.clone();
     path.push(filename);
 
     // HTTP protocol should ensure that ".." will not take place however better safe than sorry
-    path = path.canonicalize()?;
+    path = match path.canonicalize() {
+        Err(err) if err.kind() == tokio::io::ErrorKind::NotFound => return Err(GetFileFromDirectoryError::FileNotFound),
+        result => result?,
+    };
     if !path.starts_with(folder_path) {
         return Err(GetFileFromDirectoryError::PathNotInAllowedDirectory);
     }
@@ -35,48 +34,117 @@ pub(super) async fn get_file_from_directory(
     if !path.is_file() {
         return Err(GetFileFromDirectoryError::FileNotFound);
     }
-    Ok(ReaderStream::new(File::open(path).await?))
+    Ok(path)
+}
+
+/// Streams the whole file or only the given range of it
+pub(crate) async fn get_file_from_directory(
+    folder_path: PathBuf,
+    filename: &str,
+    range: Option<ByteRange>,
+) -> Result<ReaderStream<Take<File>>, GetFileFromDirectoryError> {
+    let mut file = File::open(resolve_path_in_directory(folder_path, filename).await?).await?;
+    let Some(range) = range else {
+        return Ok(ReaderStream::new(file.take(u64::MAX)));
+    };
+    file.seek(SeekFrom::Start(range.start)).await?;
+    Ok(ReaderStream::new(file.take(range.len())))
+}
+
+#[inline]
+pub(crate) async fn get_file_metadata_from_directory(
+    folder_path: PathBuf,
+    filename: &str,
+) -> Result<std::fs::Metadata, GetFileFromDirectoryError> {
+    Ok(tokio::fs::metadata(resolve_path_in_directory(folder_path, filename).await?).await?)
 }
 
+/// Subdirectory of the upload directory to which files are written until they are saved
+const STAGING_DIRECTORY: &str = "staging";
+
 #[derive(Debug)]
 pub(crate) struct FileHandle {
     id: Option<i64>,
-    path: PathBuf,
+    // Local file the upload has been written to, it is moved to the storage once the image is saved
+    staging_path: PathBuf,
+    // Key under which the image is stored
+    filename: String,
+    // Set when a row of the same image with a stored file exists, the staged file is then discarded
+    is_duplicate: bool,
+    // Set when the row has been inserted by this handle, only new images get variants
+    is_new_image: bool,
     is_saved: bool,
     connection_pool: DatabasePool,
+    storage: Arc<dyn StorageBackend>,
     image_hash: Vec<u8>,
+    format: ImageFormat,
+    dimensions: Option<(u32, u32)>,
+    variant_widths: Arc<[u32]>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum FileHandleSaveError {
     #[error("Failed to access database: {0}")]
     SqlxError(#[from] sqlx::error::Error),
-    #[error("Failed to parse file name")]
-    FileNameParsingError,
+    #[error("Failed to move file out of the staging directory: {0}")]
+    MovingFileFailed(#[from] StorageError),
 }
 
 impl FileHandle {
-    #[inline]
-    pub(crate) async fn save(&mut self) -> Result<(), FileHandleSaveError> {
-        if !self.is_saved {
-            self.is_saved = true;
-            self.id = Some(insert_image(
-                &self.connection_pool,
-                &self.image_hash,
-                self.get_name()
-                    .and_then(|v| v.to_str())
-                    .map(|v| v.to_string())
-                    .ok_or(FileHandleSaveError::FileNameParsingError)?
-            ).await?.get_id());
+    /// Inserts the image row, the file stays in the staging directory until `promote` is called.
+    /// If the same image has been inserted before the existing row is adopted instead
+    pub(crate) async fn insert(&mut self, connection: &mut DatabaseConnection) -> Result<(), FileHandleSaveError> {
+        if self.is_saved || self.id.is_some() {
+            return Ok(());
+        }
+        let image = insert_image(
+            connection,
+            &self.image_hash,
+            self.filename.clone(),
+            self.format.get_mime_type(),
+            self.dimensions,
+        ).await?;
+        self.id = Some(image.get_id());
+        self.is_new_image = image.get_filename() == self.filename;
+        if self.is_new_image {
+            // The row found when the upload was staged has been removed in the meantime
+            self.is_duplicate = false;
+        } else {
+            // A row inserted by a concurrent upload is adopted, its file is stored under its filename if still missing
+            self.filename = image.get_filename().to_string();
         }
         Ok(())
     }
-    
-    #[inline]
-    pub(crate) fn get_name(&self) -> Option<&std::ffi::OsStr> {
-        self.path.file_name()
+
+    /// Has to be called after the row inserted by `insert` has been committed
+    pub(crate) async fn promote(&mut self) -> Result<(), FileHandleSaveError> {
+        if self.is_saved {
+            return Ok(());
+        }
+        if self.is_duplicate {
+            // The same image has been saved by a concurrent upload, its file is used instead
+            if let Err(err) = tokio::fs::remove_file(&self.staging_path).await {
+                tracing::warn!("Failed to remove duplicate image file {:?}: {}", self.staging_path, err);
+            }
+            self.is_saved = true;
+            return Ok(());
+        }
+        self.storage.put_file(&self.filename, &self.staging_path).await?;
+        self.is_saved = true;
+        if let (Some(id), true, false) = (self.id, self.is_new_image, self.variant_widths.is_empty()) {
+            // Variants are not needed for the upload to succeed, originals are served until they are ready
+            tokio::spawn(generate_variants(
+                self.connection_pool.clone(),
+                self.storage.clone(),
+                id,
+                self.filename.clone(),
+                self.variant_widths.clone(),
+            ).inspect_err(move |err| tracing::error!("Failed to generate variants of image {}: {}", id, err)));
+        }
+        Ok(())
     }
 
+    #[inline]
     pub(crate) fn get_id(&self) -> Option<i64> {
         self.id
     }
@@ -85,7 +153,7 @@ impl FileHandle {
 impl Drop for FileHandle {
     fn drop(&mut self) {
         if !self.is_saved {
-            let path = self.path.clone();
+            let path = self.staging_path.clone();
             tokio::spawn(async {
                 tokio::fs::remove_file(path).await
             });
@@ -93,99 +161,277 @@ impl Drop for FileHandle {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ImageDimensionsLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+}
+
+impl ImageDimensionsLimits {
+    #[inline]
+    fn check(&self, (width, height): (u32, u32)) -> Result<(), FileHandlerServiceError> {
+        match width > self.max_width || height > self.max_height || width as u64 * height as u64 > self.max_pixels {
+            true => Err(FileHandlerServiceError::ImageDimensionsTooBig { width, height }),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Processing applied to every uploaded image
+#[derive(Debug, Clone)]
+pub(crate) struct ImageProcessingOptions {
+    pub variant_widths: Vec<u32>,
+    pub preserved_metadata: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct FileHandlerService {
     connection_pool: DatabasePool,
-    folder_path: PathBuf,
+    storage: Arc<dyn StorageBackend>,
+    staging_path: PathBuf,
     buffer_size: usize,
     max_file_size: usize,
+    image_dimensions_limits: ImageDimensionsLimits,
+    variant_widths: Arc<[u32]>,
+    preserved_metadata: Arc<[String]>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum FileHandlerServiceError {
     #[error("Tokio Io Error: {0}")]
     TokioIoError(#[from] tokio::io::Error),
-    #[error("File is not an PNG image")]
-    FileIsNotAnPNGImage,
+    #[error("Unsupported file format, accepted formats are: {}", ImageFormat::supported_formats())]
+    UnsupportedFileFormat,
     #[error("Failed to access database: {0}")]
     SqlxError(#[from] sqlx::error::Error),
     #[error("File is too big")]
     FileIsTooBig,
+    #[error("Invalid PNG image: {0}")]
+    InvalidPngImage(#[from] PngValidationError),
+    #[error("Invalid image: {0}")]
+    InvalidImageHeader(#[from] ImageHeaderError),
+    #[error("Image dimensions {width}x{height} exceed the allowed limits")]
+    ImageDimensionsTooBig { width: u32, height: u32 },
+    #[error("Failed to access image storage: {0}")]
+    StorageFailed(#[from] StorageError),
 }
 
 impl FileHandlerService {
-    pub(crate) fn new(connection_pool: DatabasePool, folder_path: &str, buffer_size: usize, max_file_size: usize) -> Option<Self> {
+    /// Uploads are staged in a subdirectory of `folder_path` which has to be on the same filesystem
+    /// as the storage when it is a `FilesystemStorage`
+    pub(crate) fn new(
+        connection_pool: DatabasePool,
+        storage: Arc<dyn StorageBackend>,
+        folder_path: &str,
+        buffer_size: usize,
+        max_file_size: usize,
+        image_dimensions_limits: ImageDimensionsLimits,
+        image_processing_options: ImageProcessingOptions,
+    ) -> Option<Self> {
         let folder_path = Path::new(folder_path).to_owned();
         match folder_path.is_dir() {
+            true if std::fs::create_dir_all(folder_path.join(STAGING_DIRECTORY)).is_err() => None,
             true => Some(Self {
                 connection_pool,
-                folder_path: folder_path.canonicalize().ok()?,
-                buffer_size: buffer_size.max(PNG_HEADER.len()),
+                storage,
+                staging_path: folder_path.canonicalize().ok()?.join(STAGING_DIRECTORY),
+                buffer_size: buffer_size.max(ImageFormat::SIGNATURE_LENGTH),
                 max_file_size,
+                image_dimensions_limits,
+                variant_widths: image_processing_options.variant_widths.into(),
+                preserved_metadata: image_processing_options.preserved_metadata.into(),
             }),
             false => None,
         }
     }
 
+    #[inline]
     pub(crate) async fn save_file(
         &self,
         content: impl Stream<Item = Result<Bytes, impl Into<Box<dyn error::Error + Send + Sync>>>>
     ) -> Result<FileHandle, FileHandlerServiceError> {
-        let mut file_path = self.folder_path.clone();
+        self.save_file_with_max_size(content, self.max_file_size).await
+    }
+
+    /// Same as `save_file` with a lower size limit, the configured maximum file size still applies
+    pub(crate) async fn save_file_with_max_size(
+        &self,
+        content: impl Stream<Item = Result<Bytes, impl Into<Box<dyn error::Error + Send + Sync>>>>,
+        max_file_size: usize,
+    ) -> Result<FileHandle, FileHandlerServiceError> {
+        let max_file_size = max_file_size.min(self.max_file_size);
+        let reader = StreamReader::new(
+            content.map_err(tokio::io::Error::other)
+        );
+        let mut buffer = vec![0; self.buffer_size];
+        pin_mut!(reader);
+        let mut read_bytes_count = 0;
+        while read_bytes_count < ImageFormat::SIGNATURE_LENGTH {
+            match reader.read(&mut buffer[read_bytes_count..ImageFormat::SIGNATURE_LENGTH]).await? {
+                0 => break,
+                n => read_bytes_count += n,
+            }
+        }
+        let format = ImageFormat::detect(&buffer[..read_bytes_count])
+            .ok_or(FileHandlerServiceError::UnsupportedFileFormat)?;
+
         let filename = uuid::Uuid::new_v4().to_string();
-        file_path.push(filename);
+        let file_path = self.staging_path.join(&filename);
         let mut file_handle = FileHandle {
             id: None,
-            path: file_path.clone(),
+            staging_path: file_path.clone(),
+            filename,
+            is_duplicate: false,
+            is_new_image: false,
             is_saved: false,
             connection_pool: self.connection_pool.clone(),
+            storage: self.storage.clone(),
             image_hash: Vec::new(),
+            format,
+            dimensions: None,
+            variant_widths: self.variant_widths.clone(),
         };
         let mut file = File::create(file_path).await?;
-        let reader = StreamReader::new(
-            content.map_err(|err| {
-                use tokio::io::{Error, ErrorKind};
-                Error::new(ErrorKind::Other, err)
-            })
-        );
         let mut hasher = sha2::Sha256::new();
-        let mut buffer = vec![0; self.buffer_size];
-        pin_mut!(reader);
-        let mut read_bytes_count = reader.read_exact(&mut buffer[..PNG_HEADER.len()]).await?;
-        if read_bytes_count < PNG_HEADER.len() || buffer[..8] != PNG_HEADER {
-            return Err(FileHandlerServiceError::FileIsNotAnPNGImage);
-        }
+        let mut png_validator = (format == ImageFormat::Png).then(PngValidator::new);
+        let mut dimensions_parser = ImageDimensionsParser::new(format);
+        let mut metadata_stripper = MetadataStripper::new(format, self.preserved_metadata.clone());
+        let mut stripped_buffer = Vec::with_capacity(self.buffer_size);
         let mut total_file_size = 0;
         while read_bytes_count != 0 {
             total_file_size += read_bytes_count;
-            if total_file_size > self.max_file_size {
+            if total_file_size > max_file_size {
                 return Err(FileHandlerServiceError::FileIsTooBig);
             }
-            hasher.update(&buffer[..read_bytes_count]);
-            file.write_all(&buffer[..read_bytes_count]).await?;
+            let png_validation_result = png_validator.as_mut()
+                .map(|png_validator| png_validator.update(&buffer[..read_bytes_count]))
+                .unwrap_or(Ok(()));
+            dimensions_parser.update(&buffer[..read_bytes_count])?;
+            // Dimensions are checked as soon as they are known so oversized images are not streamed any further
+            if file_handle.dimensions.is_none() {
+                file_handle.dimensions = png_validator.as_ref()
+                    .and_then(|png_validator| png_validator.get_dimensions())
+                    .map(|dimensions| (dimensions.width, dimensions.height))
+                    .or(dimensions_parser.get_dimensions());
+                if let Some(dimensions) = file_handle.dimensions {
+                    self.image_dimensions_limits.check(dimensions)?;
+                }
+            }
+            png_validation_result?;
+            // Metadata is stripped before hashing so the same image uploaded with different metadata is deduplicated
+            let data = match metadata_stripper.as_mut() {
+                Some(metadata_stripper) => {
+                    stripped_buffer.clear();
+                    metadata_stripper.update(&buffer[..read_bytes_count], &mut stripped_buffer);
+                    stripped_buffer.as_slice()
+                },
+                None => &buffer[..read_bytes_count],
+            };
+            hasher.update(data);
+            file.write_all(data).await?;
             read_bytes_count = reader.read(&mut buffer).await?;
         }
+        if let Some(png_validator) = png_validator {
+            png_validator.finish()?;
+        }
+        if file_handle.dimensions.is_none() {
+            return Err(ImageHeaderError::MissingDimensions.into());
+        }
+        // The already written header has to reflect the removed metadata, the file is hashed again afterwards
+        if let Some((offset, patch)) = metadata_stripper.as_ref().and_then(|v| v.finish()) {
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.write_all(&patch).await?;
+            file.flush().await?;
+            hasher = sha2::Sha256::new();
+            let mut written_file = File::open(&file_handle.staging_path).await?;
+            loop {
+                match written_file.read(&mut buffer).await? {
+                    0 => break,
+                    n => hasher.update(&buffer[..n]),
+                }
+            }
+        }
         let image_hash = hasher.finalize().to_vec();
-        let existing_image = get_image_by_hash(&self.connection_pool, &image_hash).await?;
+        file_handle.image_hash = image_hash;
+        // The row itself is adopted by `insert` within the transaction of the caller as it may be removed until then
+        let existing_image = get_image_by_hash(&self.connection_pool, &file_handle.image_hash).await?;
         if let Some(existing_image) = existing_image {
-            let mut path = self.folder_path.clone();
-            path.push(existing_image.get_filename());
-            file_handle = FileHandle{
-                id: Some(existing_image.get_id()),
-                path,
-                is_saved: true,
-                connection_pool: self.connection_pool.clone(),
-                image_hash,
-            };
-        }
-        else {
-            file_handle.image_hash = image_hash;
+            // A missing file of the existing image is replaced by the uploaded one, otherwise it is discarded
+            file_handle.is_duplicate = self.storage.exists(existing_image.get_filename()).await?;
         }
         Ok(file_handle)
     }
     
+    /// Removes files left in the staging directory by uploads which have been interrupted by a crash or a shutdown
+    pub(crate) async fn sweep_staging_directory(&self, max_age: Duration) -> Result<(), tokio::io::Error> {
+        let mut entries = tokio::fs::read_dir(&self.staging_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let age = SystemTime::now().duration_since(metadata.modified()?).unwrap_or_default();
+            if !metadata.is_file() || age < max_age {
+                continue;
+            }
+            match tokio::fs::remove_file(entry.path()).await {
+                Ok(()) => tracing::info!("Removed abandoned staging file {:?}", entry.file_name()),
+                Err(err) => tracing::warn!("Failed to remove abandoned staging file {:?}: {}", entry.file_name(), err),
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) async fn remove_file(&self, filename: &str) -> Result<(), StorageError> {
+        self.storage.delete(filename).await
+    }
+
+    /// Removes the image row together with its variants and their files if no post references it anymore
+    pub(crate) async fn release_image(&self, id: i64) -> Result<(), sqlx::Error> {
+        // Variant rows are removed by the cascade, their filenames have to be read beforehand
+        let variants = image::get_image_variants(&self.connection_pool, id).await?;
+        if let Some(image) = image::delete_image_if_unreferenced(&self.connection_pool, id).await? {
+            self.remove_image_files(&image, &variants).await;
+        }
+        Ok(())
+    }
+
+    /// Failures are only logged as the rows have already been removed
+    async fn remove_image_files(&self, image: &Image, variants: &[ImageVariant]) {
+        let filenames = std::iter::once(image.get_filename())
+            .chain(variants.iter().map(|v| v.get_filename()));
+        for filename in filenames {
+            if let Err(err) = self.remove_file(filename).await {
+                tracing::warn!("Failed to remove image file {}: {}", filename, err);
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) async fn get_image(&self, filename: &str) -> Result<Option<Image>, sqlx::Error> {
+        get_image_by_filename(&self.connection_pool, filename).await
+    }
+
+    /// Returns the narrowest variant of the image which is at least `min_width` pixels wide
+    #[inline]
+    pub(crate) async fn get_image_variant(&self, filename: &str, min_width: u32) -> Result<Option<ImageVariant>, sqlx::Error> {
+        get_image_variant(&self.connection_pool, filename, min_width).await
+    }
+
     #[inline]
-    pub(crate) async fn get_file(&self, filename: &str) -> Result<ReaderStream<File>, GetFileFromDirectoryError> {
-        get_file_from_directory(self.folder_path.clone(), filename).await
+    pub(super) async fn list_files(&self) -> Result<Vec<StoredObject>, StorageError> {
+        self.storage.list().await
+    }
+
+    #[inline]
+    pub(crate) async fn get_file_metadata(&self, filename: &str) -> Result<StoredObject, StorageError> {
+        self.storage.head(filename).await
+    }
+
+    #[inline]
+    pub(crate) async fn get_file(&self, filename: &str, range: Option<ByteRange>) -> Result<ByteStream, StorageError> {
+        match range {
+            Some(range) => self.storage.get_range(filename, range).await,
+            None => self.storage.get(filename).await,
+        }
     }
 }
@@ -0,0 +1,50 @@
+use std::io::Cursor;
+use sha2::{Digest, Sha256};
+
+/// Amount of cells in a row and in a column
+const GRID_SIZE: u32 = 5;
+const CELL_SIZE: u32 = 48;
+const MARGIN: u32 = CELL_SIZE / 2;
+const IMAGE_SIZE: u32 = GRID_SIZE * CELL_SIZE + 2 * MARGIN;
+const BACKGROUND: image::Rgb<u8> = image::Rgb([240, 240, 240]);
+
+#[inline]
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> image::Rgb<u8> {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    image::Rgb([r, g, b].map(|v| ((v + m) * 255.0).round() as u8))
+}
+
+/// Renders a PNG image of a horizontally symmetric grid of cells, their pattern and colour are derived
+/// from a hash of the seed so the same seed always gives the same image
+pub(crate) fn render_identicon(seed: &str) -> Result<Vec<u8>, image::ImageError> {
+    let hash = Sha256::digest(seed.as_bytes());
+    let hue = f32::from(u16::from_be_bytes([hash[0], hash[1]]) % 360);
+    let foreground = hsl_to_rgb(hue, 0.55, 0.55);
+    // Only the left half and the middle column are hashed, the right half mirrors the left one
+    let half_width = GRID_SIZE.div_ceil(2);
+    let is_filled = |column: u32, row: u32| {
+        let column = column.min(GRID_SIZE - 1 - column);
+        hash[(2 + row * half_width + column) as usize] & 1 == 1
+    };
+    let image = image::RgbImage::from_fn(IMAGE_SIZE, IMAGE_SIZE, |x, y| {
+        let inside = (MARGIN..IMAGE_SIZE - MARGIN).contains(&x) && (MARGIN..IMAGE_SIZE - MARGIN).contains(&y);
+        if inside && is_filled((x - MARGIN) / CELL_SIZE, (y - MARGIN) / CELL_SIZE) {
+            foreground
+        } else {
+            BACKGROUND
+        }
+    });
+    let mut content = Vec::new();
+    image.write_to(&mut Cursor::new(&mut content), image::ImageFormat::Png)?;
+    Ok(content)
+}
@@ -0,0 +1,177 @@
+use super::image_format::ImageFormat;
+
+const GIF_HEADER_LENGTH: usize = 10;
+const WEBP_HEADER_LENGTH: usize = 30;
+// Start of frame markers, the remaining C4, C8 and CC markers from that range are DHT, JPG and DAC
+const JPEG_SOF_MARKERS: [u8; 13] = [0xc0, 0xc1, 0xc2, 0xc3, 0xc5, 0xc6, 0xc7, 0xc9, 0xca, 0xcb, 0xcd, 0xce, 0xcf];
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ImageHeaderError {
+    #[error("Malformed {0} header")]
+    MalformedHeader(&'static str),
+    #[error("Could not determine image dimensions")]
+    MissingDimensions,
+}
+
+type FixedHeaderParser = fn(&[u8]) -> Result<(u32, u32), ImageHeaderError>;
+
+#[derive(Debug)]
+enum JpegState {
+    // Waiting for the 0xFF byte starting a marker (SOI has already been consumed)
+    Marker,
+    MarkerType,
+    SegmentLength { marker: u8 },
+    SkipSegment { remaining: usize },
+    StartOfFrame,
+}
+
+/// Incrementally reads width and height from the headers of JPEG, GIF and WebP files.
+/// PNG dimensions are read by `PngValidator` together with the rest of the structure
+#[derive(Debug)]
+pub(crate) struct ImageDimensionsParser {
+    format: ImageFormat,
+    header: Vec<u8>,
+    position: usize,
+    jpeg_state: JpegState,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl ImageDimensionsParser {
+    #[inline]
+    pub(crate) fn new(format: ImageFormat) -> Self {
+        Self {
+            format,
+            header: Vec::new(),
+            position: 0,
+            jpeg_state: JpegState::Marker,
+            dimensions: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_dimensions(&self) -> Option<(u32, u32)> {
+        self.dimensions
+    }
+
+    /// Has to be fed with the file from its beginning, does nothing once the dimensions are known
+    pub(crate) fn update(&mut self, data: &[u8]) -> Result<(), ImageHeaderError> {
+        if self.dimensions.is_some() {
+            return Ok(());
+        }
+        match self.format {
+            ImageFormat::Png => Ok(()),
+            ImageFormat::Gif => self.update_fixed_header(data, GIF_HEADER_LENGTH, Self::parse_gif_header),
+            ImageFormat::WebP => self.update_fixed_header(data, WEBP_HEADER_LENGTH, Self::parse_webp_header),
+            ImageFormat::Jpeg => self.update_jpeg(data),
+        }
+    }
+
+    fn update_fixed_header(
+        &mut self,
+        data: &[u8],
+        length: usize,
+        parse: FixedHeaderParser,
+    ) -> Result<(), ImageHeaderError> {
+        let taken = (length - self.header.len()).min(data.len());
+        self.header.extend_from_slice(&data[..taken]);
+        if self.header.len() == length {
+            self.dimensions = Some(parse(&self.header)?);
+        }
+        Ok(())
+    }
+
+    // Logical screen descriptor directly follows the 6 byte signature
+    fn parse_gif_header(header: &[u8]) -> Result<(u32, u32), ImageHeaderError> {
+        Ok((
+            u16::from_le_bytes([header[6], header[7]]) as u32,
+            u16::from_le_bytes([header[8], header[9]]) as u32,
+        ))
+    }
+
+    // The first chunk following the RIFF header determines the encoding of the image
+    fn parse_webp_header(header: &[u8]) -> Result<(u32, u32), ImageHeaderError> {
+        let read_u24 = |index: usize| u32::from_le_bytes([header[index], header[index + 1], header[index + 2], 0]);
+        match &header[12..16] {
+            b"VP8X" => Ok((read_u24(24) + 1, read_u24(27) + 1)),
+            b"VP8L" if header[20] == 0x2f => {
+                let bits = u32::from_le_bytes([header[21], header[22], header[23], header[24]]);
+                Ok(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            },
+            b"VP8 " if header[23..26] == [0x9d, 0x01, 0x2a] => Ok((
+                u16::from_le_bytes([header[26], header[27]]) as u32 & 0x3fff,
+                u16::from_le_bytes([header[28], header[29]]) as u32 & 0x3fff,
+            )),
+            _ => Err(ImageHeaderError::MalformedHeader("WebP")),
+        }
+    }
+
+    // Walks the segments of the file until the first start of frame segment
+    fn update_jpeg(&mut self, data: &[u8]) -> Result<(), ImageHeaderError> {
+        // The first two bytes are the SOI marker which has been checked by the format detection
+        let skipped = 2usize.saturating_sub(self.position).min(data.len());
+        self.position += skipped;
+        let mut data = &data[skipped..];
+        while let Some((&byte, rest)) = data.split_first() {
+            match self.jpeg_state {
+                JpegState::Marker => {
+                    if byte != 0xff {
+                        return Err(ImageHeaderError::MalformedHeader("JPEG"));
+                    }
+                    self.jpeg_state = JpegState::MarkerType;
+                    data = rest;
+                },
+                JpegState::MarkerType => {
+                    self.jpeg_state = match byte {
+                        // Fill bytes
+                        0xff => JpegState::MarkerType,
+                        // Markers without a payload
+                        0x01 | 0xd0..=0xd7 => JpegState::Marker,
+                        0x00 | 0xd8 | 0xd9 | 0xda => return Err(ImageHeaderError::MissingDimensions),
+                        marker => JpegState::SegmentLength { marker },
+                    };
+                    data = rest;
+                },
+                JpegState::SegmentLength { marker } => {
+                    let taken = (2 - self.header.len()).min(data.len());
+                    self.header.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    if self.header.len() < 2 {
+                        continue;
+                    }
+                    let length = u16::from_be_bytes([self.header[0], self.header[1]]) as usize;
+                    self.header.clear();
+                    if length < 2 {
+                        return Err(ImageHeaderError::MalformedHeader("JPEG"));
+                    }
+                    self.jpeg_state = match JPEG_SOF_MARKERS.contains(&marker) {
+                        true if length < 7 => return Err(ImageHeaderError::MalformedHeader("JPEG")),
+                        true => JpegState::StartOfFrame,
+                        false => JpegState::SkipSegment { remaining: length - 2 },
+                    };
+                },
+                JpegState::SkipSegment { remaining } => {
+                    let taken = remaining.min(data.len());
+                    data = &data[taken..];
+                    self.jpeg_state = match remaining - taken {
+                        0 => JpegState::Marker,
+                        remaining => JpegState::SkipSegment { remaining },
+                    };
+                },
+                JpegState::StartOfFrame => {
+                    // Sample precision followed by height and width
+                    let taken = (5 - self.header.len()).min(data.len());
+                    self.header.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    if self.header.len() == 5 {
+                        self.dimensions = Some((
+                            u16::from_be_bytes([self.header[3], self.header[4]]) as u32,
+                            u16::from_be_bytes([self.header[1], self.header[2]]) as u32,
+                        ));
+                        return Ok(());
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
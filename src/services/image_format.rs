@@ -0,0 +1,56 @@
+/// Image formats accepted for upload, recognised by the magic bytes at the beginning of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+}
+
+impl ImageFormat {
+    pub(crate) const ALL: [ImageFormat; 4] = [Self::Png, Self::Jpeg, Self::Gif, Self::WebP];
+
+    /// Amount of bytes needed to recognise any of the supported formats
+    pub(crate) const SIGNATURE_LENGTH: usize = 12;
+
+    /// Returns `None` if the header does not match any of the supported formats
+    pub(crate) fn detect(header: &[u8]) -> Option<Self> {
+        const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+        const JPEG_SIGNATURE: [u8; 3] = [0xff, 0xd8, 0xff];
+        match header {
+            _ if header.starts_with(&PNG_SIGNATURE) => Some(Self::Png),
+            _ if header.starts_with(&JPEG_SIGNATURE) => Some(Self::Jpeg),
+            _ if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") => Some(Self::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::WebP),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::WebP => "image/webp",
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_name(&self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::Gif => "GIF",
+            Self::WebP => "WebP",
+        }
+    }
+
+    /// Comma separated list of names of all supported formats
+    pub(crate) fn supported_formats() -> String {
+        Self::ALL.iter()
+            .map(|format| format.get_name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
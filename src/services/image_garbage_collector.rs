@@ -0,0 +1,164 @@
+use std::{collections::{HashMap, HashSet}, fmt, time::{Duration, SystemTime}};
+use crate::db::{image, DatabasePool};
+use super::{file_handler_service::FileHandlerService, storage::StorageError};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ImageGarbageCollectorError {
+    #[error("Failed to access image storage: {0}")]
+    StorageFailed(#[from] StorageError),
+    #[error("Failed to access database: {0}")]
+    SqlxError(#[from] sqlx::Error),
+}
+
+/// Filenames of everything which has been (or in a dry run would be) removed
+#[derive(Debug, Default)]
+pub(crate) struct GarbageCollectionReport {
+    pub dry_run: bool,
+    pub unreferenced_images: Vec<String>,
+    pub orphaned_files: Vec<String>,
+    pub missing_files: Vec<String>,
+    /// Images used by posts whose file is missing, they are only reported as removing them would strip the posts
+    pub referenced_missing_files: Vec<String>,
+}
+
+impl fmt::Display for GarbageCollectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Image garbage collection report{}", if self.dry_run { " (dry run, nothing removed)" } else { "" })?;
+        let sections = [
+            ("Images not referenced by any post", &self.unreferenced_images),
+            ("Files without an image row", &self.orphaned_files),
+            ("Image rows without a file", &self.missing_files),
+            ("Images used by posts without a file (kept)", &self.referenced_missing_files),
+        ];
+        for (title, filenames) in sections {
+            writeln!(f, "{} ({}):", title, filenames.len())?;
+            for filename in filenames {
+                writeln!(f, "  {}", filename)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reconciles `Images` and `ImageVariants` rows with the stored files.
+/// Files and image rows younger than the grace period are left alone as they may belong to uploads which are still in progress,
+/// rows are committed before their files are stored
+#[derive(Debug, Clone)]
+pub(crate) struct ImageGarbageCollector {
+    connection_pool: DatabasePool,
+    file_handler_service: FileHandlerService,
+    interval: Option<Duration>,
+    grace_period: Duration,
+}
+
+impl ImageGarbageCollector {
+    #[inline]
+    pub(crate) fn new(
+        connection_pool: DatabasePool,
+        file_handler_service: FileHandlerService,
+        interval: Option<Duration>,
+        grace_period: Duration,
+    ) -> Self {
+        Self {
+            connection_pool,
+            file_handler_service,
+            interval,
+            grace_period,
+        }
+    }
+
+    /// Runs the collection periodically in the background, does nothing if no interval is configured
+    pub(crate) fn start(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let garbage_collector = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                interval.tick().await;
+                match garbage_collector.collect_garbage(false).await {
+                    Ok(report) => tracing::info!("{}", report),
+                    Err(err) => tracing::error!("Image garbage collection failed: {}", err),
+                }
+            }
+        });
+    }
+
+    /// Returns the age of every stored file
+    async fn get_uploaded_files(&self) -> Result<HashMap<String, Duration>, StorageError> {
+        Ok(self.file_handler_service.list_files().await?
+            .into_iter()
+            .map(|object| (object.key, SystemTime::now().duration_since(object.last_modified).unwrap_or_default()))
+            .collect())
+    }
+
+    pub(crate) async fn collect_garbage(&self, dry_run: bool) -> Result<GarbageCollectionReport, ImageGarbageCollectorError> {
+        let mut report = GarbageCollectionReport { dry_run, ..Default::default() };
+        // Rows are read before the files are listed, so the file of every row committed in the meantime is listed as well
+        let images = image::get_all_images(&self.connection_pool).await?;
+        let variants = image::get_all_image_variants(&self.connection_pool).await?;
+        let files = self.get_uploaded_files().await?;
+        let is_expired = |filename: &str| files.get(filename).is_some_and(|age| *age >= self.grace_period);
+        let is_image_expired = |image: &image::Image| (chrono::Utc::now() - image.get_created_at()).to_std()
+            .is_ok_and(|age| age >= self.grace_period);
+
+        let mut missing_images = HashSet::new();
+        for image in images.iter().filter(|v| !files.contains_key(v.get_filename())) {
+            missing_images.insert(image.get_id());
+            if !is_image_expired(image) {
+                continue;
+            }
+            if image::is_image_referenced(&self.connection_pool, image.get_id()).await? {
+                report.referenced_missing_files.push(image.get_filename().to_string());
+                continue;
+            }
+            report.missing_files.push(image.get_filename().to_string());
+            if dry_run {
+                continue;
+            }
+            let image_variants = image::get_image_variants(&self.connection_pool, image.get_id()).await?;
+            // Checks the references again in case a post started using the image in the meantime
+            if image::delete_image_if_unreferenced(&self.connection_pool, image.get_id()).await?.is_some() {
+                for variant in image_variants {
+                    self.remove_file(variant.get_filename()).await;
+                }
+            }
+        }
+        for variant in variants.iter().filter(|v| !files.contains_key(v.get_filename())) {
+            report.missing_files.push(variant.get_filename().to_string());
+            if !dry_run {
+                image::delete_image_variant(&self.connection_pool, variant.get_filename()).await?;
+            }
+        }
+
+        let unreferenced_images = image::get_unreferenced_images(&self.connection_pool).await?;
+        for image in unreferenced_images.iter()
+            .filter(|v| !missing_images.contains(&v.get_id()) && is_expired(v.get_filename())) {
+            report.unreferenced_images.push(image.get_filename().to_string());
+            if !dry_run {
+                // Checks the references again in case a post started using the image in the meantime
+                self.file_handler_service.release_image(image.get_id()).await?;
+            }
+        }
+
+        let known_files = images.iter().map(|v| v.get_filename())
+            .chain(variants.iter().map(|v| v.get_filename()))
+            .collect::<HashSet<_>>();
+        for filename in files.keys().filter(|v| !known_files.contains(v.as_str()) && is_expired(v)) {
+            report.orphaned_files.push(filename.clone());
+            if !dry_run {
+                self.remove_file(filename).await;
+            }
+        }
+        report.orphaned_files.sort();
+        Ok(report)
+    }
+
+    #[inline]
+    async fn remove_file(&self, filename: &str) {
+        if let Err(err) = self.file_handler_service.remove_file(filename).await {
+            tracing::warn!("Failed to remove image file {}: {}", filename, err);
+        }
+    }
+}
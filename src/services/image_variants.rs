@@ -0,0 +1,90 @@
+use std::{io::Cursor, sync::Arc};
+use axum::body::Bytes;
+use futures::TryStreamExt;
+use image::{DynamicImage, ImageReader};
+use crate::db::{image::insert_image_variant, DatabasePool};
+use super::{image_format::ImageFormat, storage::{StorageBackend, StorageError}};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ImageVariantsError {
+    #[error("Failed to process image: {0}")]
+    ImageProcessingFailed(#[from] image::ImageError),
+    #[error("Tokio IO error: {0}")]
+    TokioIoError(#[from] tokio::io::Error),
+    #[error("Failed to access image storage: {0}")]
+    StorageFailed(#[from] StorageError),
+    #[error("Failed to access database: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("Image processing task failed: {0}")]
+    TaskFailed(#[from] tokio::task::JoinError),
+}
+
+struct EncodedVariant {
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+    data: Vec<u8>,
+}
+
+// Images with transparency are encoded as PNG, all others as JPEG which is considerably smaller for photos
+fn render_variants(source: &[u8], widths: &[u32]) -> Result<Vec<EncodedVariant>, image::ImageError> {
+    let image = ImageReader::new(Cursor::new(source)).with_guessed_format()?.decode()?;
+    let has_alpha = image.color().has_alpha();
+    widths.iter()
+        .filter(|width| **width < image.width())
+        .map(|width| {
+            let variant = image.thumbnail(*width, u32::MAX);
+            let mut data = Cursor::new(Vec::new());
+            let format = match has_alpha {
+                true => {
+                    variant.write_to(&mut data, image::ImageFormat::Png)?;
+                    ImageFormat::Png
+                },
+                false => {
+                    DynamicImage::ImageRgb8(variant.to_rgb8()).write_to(&mut data, image::ImageFormat::Jpeg)?;
+                    ImageFormat::Jpeg
+                },
+            };
+            Ok(EncodedVariant {
+                width: variant.width(),
+                height: variant.height(),
+                format,
+                data: data.into_inner(),
+            })
+        })
+        .collect()
+}
+
+/// Generates downscaled copies of the stored image, widths not smaller than the original are skipped.
+/// Variants are stored under the filename of the original with their width appended
+pub(super) async fn generate_variants(
+    connection_pool: DatabasePool,
+    storage: Arc<dyn StorageBackend>,
+    image_id: i64,
+    filename: String,
+    widths: Arc<[u32]>,
+) -> Result<(), ImageVariantsError> {
+    let source = storage.get(&filename).await?
+        .try_fold(Vec::new(), |mut source, chunk| async move {
+            source.extend_from_slice(&chunk);
+            Ok(source)
+        }).await?;
+    let variants = tokio::task::spawn_blocking(move || render_variants(&source, &widths)).await??;
+    for variant in variants {
+        let variant_filename = format!("{}_{}", filename, variant.width);
+        let data = Bytes::from(variant.data);
+        storage.put(&variant_filename, Box::pin(futures::stream::once(async { Ok(data) }))).await?;
+        if let Err(err) = insert_image_variant(
+            &connection_pool,
+            image_id,
+            (variant.width, variant.height),
+            &variant_filename,
+            variant.format.get_mime_type(),
+        ).await {
+            // The original could have been removed in the meantime
+            storage.delete(&variant_filename).await.ok();
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,485 @@
+use std::sync::Arc;
+use super::image_format::ImageFormat;
+
+const PNG_SIGNATURE_LENGTH: usize = 8;
+// Textual data, EXIF, modification time and embedded colour profiles which can identify the device
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 6] = [b"tEXt", b"iTXt", b"zTXt", b"eXIf", b"tIME", b"iCCP"];
+const JPEG_SOS: u8 = 0xda;
+const JPEG_COM: u8 = 0xfe;
+// RIFF header followed by the form type
+const WEBP_HEADER_LENGTH: usize = 12;
+const WEBP_METADATA_CHUNKS: [&[u8; 4]; 2] = [b"EXIF", b"XMP "];
+// Flags of the extended format header announcing the metadata chunks
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+// Header and logical screen descriptor
+const GIF_SCREEN_DESCRIPTOR_LENGTH: usize = 13;
+// Image separator followed by the image descriptor
+const GIF_IMAGE_DESCRIPTOR_LENGTH: usize = 10;
+const GIF_EXTENSION: u8 = 0x21;
+const GIF_IMAGE_SEPARATOR: u8 = 0x2c;
+const GIF_COMMENT_LABEL: u8 = 0xfe;
+const GIF_APPLICATION_LABEL: u8 = 0xff;
+// Introducer, label and the first sub-block holding the application identifier and authentication code
+const GIF_APPLICATION_HEADER_LENGTH: usize = 14;
+const GIF_XMP_APPLICATION: &[u8; 11] = b"XMP DataXMP";
+
+#[derive(Debug)]
+enum State {
+    Signature { remaining: usize },
+    Header,
+    Copy { remaining: u64 },
+    Skip { remaining: u64 },
+    JpegMarker,
+    JpegMarkerType,
+    JpegSegmentLength { marker: u8 },
+    WebPHeader,
+    // The first byte of the VP8X chunk holds the feature flags
+    WebPFlags { remaining: u64 },
+    GifScreenDescriptor,
+    // Colour table, followed by sub-blocks if it belongs to an image as the LZW code size is copied with it
+    GifTable { remaining: u64, sub_blocks: bool },
+    GifBlock,
+    GifExtension,
+    GifApplication,
+    GifImageDescriptor,
+    // Size of the next sub-block, a zero size terminates the block
+    GifSubBlock { skip: bool },
+    // Everything following the first scan or malformed data is passed through unchanged
+    Passthrough,
+}
+
+/// Incrementally removes metadata from PNG, JPEG, WebP and GIF files, the file can be fed in slices of any size.
+/// PNG ancillary chunks carrying metadata, JPEG APP1-APP15 and COM segments, WebP EXIF and XMP chunks
+/// and GIF comment (`COM`) and XMP application extensions are dropped unless their name (e.g. `iCCP`, `APP2` or `XMP`)
+/// is listed in `preserved`.
+/// The input is expected to be validated separately, malformed data is never rejected here
+#[derive(Debug)]
+pub(crate) struct MetadataStripper {
+    format: ImageFormat,
+    preserved: Arc<[String]>,
+    state: State,
+    // Bytes of the currently parsed chunk header or segment length
+    header: Vec<u8>,
+    // Size declared by the RIFF header of a WebP file and the amount of bytes removed from it
+    riff_size: u32,
+    stripped: u32,
+}
+
+impl MetadataStripper {
+    /// Returns `None` for formats whose metadata is not stripped
+    #[inline]
+    pub(crate) fn new(format: ImageFormat, preserved: Arc<[String]>) -> Option<Self> {
+        let state = match format {
+            ImageFormat::Png => State::Signature { remaining: PNG_SIGNATURE_LENGTH },
+            // SOI marker
+            ImageFormat::Jpeg => State::Signature { remaining: 2 },
+            ImageFormat::WebP => State::WebPHeader,
+            ImageFormat::Gif => State::GifScreenDescriptor,
+        };
+        Some(Self {
+            format,
+            preserved,
+            state,
+            header: Vec::with_capacity(WEBP_HEADER_LENGTH),
+            riff_size: 0,
+            stripped: 0,
+        })
+    }
+
+    #[inline]
+    fn is_preserved(&self, name: &str) -> bool {
+        self.preserved.iter().any(|v| v == name)
+    }
+
+    /// Returns the offset and the bytes which have to overwrite the already written output once all data has been fed,
+    /// as the RIFF header of a WebP file declares the size of the whole file
+    pub(crate) fn finish(&self) -> Option<(u64, [u8; 4])> {
+        match (self.format, self.stripped) {
+            (ImageFormat::WebP, 1..) => Some((4, self.riff_size.saturating_sub(self.stripped).to_le_bytes())),
+            _ => None,
+        }
+    }
+
+    /// Appends the data without stripped metadata to `output`
+    pub(crate) fn update(&mut self, mut data: &[u8], output: &mut Vec<u8>) {
+        while let Some((&byte, rest)) = data.split_first() {
+            match self.state {
+                State::Signature { remaining } => {
+                    let taken = remaining.min(data.len());
+                    output.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    self.state = match (remaining - taken, self.format) {
+                        (0, ImageFormat::Png) => State::Header,
+                        (0, _) => State::JpegMarker,
+                        (remaining, _) => State::Signature { remaining },
+                    };
+                },
+                State::Header => {
+                    let taken = (8 - self.header.len()).min(data.len());
+                    self.header.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    if self.header.len() == 8 {
+                        match self.format {
+                            ImageFormat::WebP => self.start_webp_chunk(output),
+                            _ => self.start_png_chunk(output),
+                        }
+                    }
+                },
+                State::Copy { remaining } | State::Skip { remaining } => {
+                    let taken = remaining.min(data.len() as u64) as usize;
+                    match self.state {
+                        State::Copy { .. } => output.extend_from_slice(&data[..taken]),
+                        _ => self.stripped = self.stripped.saturating_add(taken as u32),
+                    }
+                    data = &data[taken..];
+                    let remaining = remaining - taken as u64;
+                    self.state = match (remaining, &self.state, self.format) {
+                        (0, _, ImageFormat::Png | ImageFormat::WebP) => State::Header,
+                        (0, State::Copy { .. }, ImageFormat::Gif) => State::GifSubBlock { skip: false },
+                        (0, _, ImageFormat::Gif) => State::GifSubBlock { skip: true },
+                        (0, _, _) => State::JpegMarker,
+                        (remaining, State::Copy { .. }, _) => State::Copy { remaining },
+                        (remaining, _, _) => State::Skip { remaining },
+                    };
+                },
+                State::JpegMarker => {
+                    if byte != 0xff {
+                        self.state = State::Passthrough;
+                        continue;
+                    }
+                    self.state = State::JpegMarkerType;
+                    data = rest;
+                },
+                State::JpegMarkerType => {
+                    self.state = match byte {
+                        // Fill bytes
+                        0xff => State::JpegMarkerType,
+                        // Markers without a payload
+                        0x01 | 0xd0..=0xd7 => {
+                            output.extend_from_slice(&[0xff, byte]);
+                            State::JpegMarker
+                        },
+                        0x00 | 0xd8 | 0xd9 | JPEG_SOS => {
+                            output.extend_from_slice(&[0xff, byte]);
+                            State::Passthrough
+                        },
+                        marker => State::JpegSegmentLength { marker },
+                    };
+                    data = rest;
+                },
+                State::JpegSegmentLength { marker } => {
+                    let taken = (2 - self.header.len()).min(data.len());
+                    self.header.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    if self.header.len() == 2 {
+                        self.start_jpeg_segment(marker, output);
+                    }
+                },
+                State::WebPHeader => {
+                    let taken = (WEBP_HEADER_LENGTH - self.header.len()).min(data.len());
+                    self.header.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    if self.header.len() == WEBP_HEADER_LENGTH {
+                        self.riff_size = u32::from_le_bytes([self.header[4], self.header[5], self.header[6], self.header[7]]);
+                        output.extend_from_slice(&self.header);
+                        self.header.clear();
+                        self.state = State::Header;
+                    }
+                },
+                State::WebPFlags { remaining } => {
+                    let mut flags = byte;
+                    for (name, flag) in [("EXIF", WEBP_EXIF_FLAG), ("XMP", WEBP_XMP_FLAG)] {
+                        if !self.is_preserved(name) {
+                            flags &= !flag;
+                        }
+                    }
+                    output.push(flags);
+                    data = rest;
+                    self.state = match remaining - 1 {
+                        0 => State::Header,
+                        remaining => State::Copy { remaining },
+                    };
+                },
+                State::GifScreenDescriptor => {
+                    if self.fill_header(&mut data, GIF_SCREEN_DESCRIPTOR_LENGTH) {
+                        output.extend_from_slice(&self.header);
+                        let remaining = Self::get_gif_table_size(self.header[10]);
+                        self.header.clear();
+                        self.state = State::GifTable { remaining, sub_blocks: false };
+                    }
+                },
+                State::GifTable { remaining, sub_blocks } => {
+                    let taken = remaining.min(data.len() as u64) as usize;
+                    output.extend_from_slice(&data[..taken]);
+                    data = &data[taken..];
+                    self.state = match (remaining - taken as u64, sub_blocks) {
+                        (0, true) => State::GifSubBlock { skip: false },
+                        (0, false) => State::GifBlock,
+                        (remaining, sub_blocks) => State::GifTable { remaining, sub_blocks },
+                    };
+                },
+                // The introducer is left in the data as it is collected together with the rest of the block header
+                State::GifBlock => {
+                    self.state = match byte {
+                        GIF_EXTENSION => State::GifExtension,
+                        GIF_IMAGE_SEPARATOR => State::GifImageDescriptor,
+                        // Trailer or malformed data
+                        _ => State::Passthrough,
+                    };
+                },
+                State::GifExtension => {
+                    if self.fill_header(&mut data, 2) {
+                        self.start_gif_extension(output);
+                    }
+                },
+                State::GifApplication => {
+                    if self.fill_header(&mut data, GIF_APPLICATION_HEADER_LENGTH) {
+                        self.start_gif_application(output);
+                    }
+                },
+                State::GifImageDescriptor => {
+                    if self.fill_header(&mut data, GIF_IMAGE_DESCRIPTOR_LENGTH) {
+                        output.extend_from_slice(&self.header);
+                        // The LZW minimum code size precedes the sub-blocks of the image data
+                        let remaining = Self::get_gif_table_size(self.header[9]) + 1;
+                        self.header.clear();
+                        self.state = State::GifTable { remaining, sub_blocks: true };
+                    }
+                },
+                State::GifSubBlock { skip } => {
+                    if !skip {
+                        output.push(byte);
+                    }
+                    data = rest;
+                    self.state = match (byte, skip) {
+                        (0, _) => State::GifBlock,
+                        (size, false) => State::Copy { remaining: size as u64 },
+                        (size, true) => State::Skip { remaining: size as u64 },
+                    };
+                },
+                State::Passthrough => {
+                    output.extend_from_slice(data);
+                    return;
+                },
+            }
+        }
+    }
+
+    /// Moves bytes from `data` to the header until it is `length` bytes long, returns whether it is complete
+    #[inline]
+    fn fill_header(&mut self, data: &mut &[u8], length: usize) -> bool {
+        let taken = length.saturating_sub(self.header.len()).min(data.len());
+        self.header.extend_from_slice(&data[..taken]);
+        *data = &data[taken..];
+        self.header.len() >= length
+    }
+
+    /// Size of the colour table announced by the packed fields of a screen or image descriptor
+    #[inline]
+    fn get_gif_table_size(packed_fields: u8) -> u64 {
+        match packed_fields & 0x80 {
+            0 => 0,
+            _ => 3 << ((packed_fields & 0x07) + 1),
+        }
+    }
+
+    fn start_gif_extension(&mut self, output: &mut Vec<u8>) {
+        // The application identifier is needed to decide, the header is completed first
+        if self.header[1] == GIF_APPLICATION_LABEL {
+            self.state = State::GifApplication;
+            return;
+        }
+        self.state = match self.header[1] {
+            GIF_COMMENT_LABEL if !self.is_preserved("COM") => State::GifSubBlock { skip: true },
+            _ => {
+                output.extend_from_slice(&self.header);
+                State::GifSubBlock { skip: false }
+            },
+        };
+        self.header.clear();
+    }
+
+    fn start_gif_application(&mut self, output: &mut Vec<u8>) {
+        // The identifier has to fill the first sub-block, anything else is malformed
+        if self.header[2] != 11 {
+            output.extend_from_slice(&self.header);
+            self.header.clear();
+            self.state = State::Passthrough;
+            return;
+        }
+        let is_stripped = &self.header[3..] == GIF_XMP_APPLICATION && !self.is_preserved("XMP");
+        if !is_stripped {
+            output.extend_from_slice(&self.header);
+        }
+        self.header.clear();
+        self.state = State::GifSubBlock { skip: is_stripped };
+    }
+
+    fn start_png_chunk(&mut self, output: &mut Vec<u8>) {
+        let length = u32::from_be_bytes([self.header[0], self.header[1], self.header[2], self.header[3]]);
+        let chunk_type = [self.header[4], self.header[5], self.header[6], self.header[7]];
+        // Data followed by CRC
+        let remaining = length as u64 + 4;
+        let is_stripped = PNG_METADATA_CHUNKS.contains(&&chunk_type)
+            && !self.is_preserved(&String::from_utf8_lossy(&chunk_type));
+        self.state = match is_stripped {
+            true => State::Skip { remaining },
+            false => {
+                output.extend_from_slice(&self.header);
+                State::Copy { remaining }
+            },
+        };
+        self.header.clear();
+    }
+
+    fn start_webp_chunk(&mut self, output: &mut Vec<u8>) {
+        let chunk_type = [self.header[0], self.header[1], self.header[2], self.header[3]];
+        let length = u32::from_le_bytes([self.header[4], self.header[5], self.header[6], self.header[7]]);
+        // Chunks are padded to an even size
+        let remaining = length as u64 + (length & 1) as u64;
+        let is_stripped = WEBP_METADATA_CHUNKS.contains(&&chunk_type)
+            && !self.is_preserved(String::from_utf8_lossy(&chunk_type).trim_end());
+        self.state = match (is_stripped, &chunk_type, remaining) {
+            (true, _, _) => {
+                self.stripped = self.stripped.saturating_add(8);
+                State::Skip { remaining }
+            },
+            (false, b"VP8X", 1..) => {
+                output.extend_from_slice(&self.header);
+                State::WebPFlags { remaining }
+            },
+            (false, _, _) => {
+                output.extend_from_slice(&self.header);
+                State::Copy { remaining }
+            },
+        };
+        self.header.clear();
+    }
+
+    fn start_jpeg_segment(&mut self, marker: u8, output: &mut Vec<u8>) {
+        let length = u16::from_be_bytes([self.header[0], self.header[1]]);
+        let name = match marker {
+            0xe1..=0xef => Some(format!("APP{}", marker - 0xe0)),
+            JPEG_COM => Some("COM".to_string()),
+            _ => None,
+        };
+        let is_stripped = name.is_some_and(|name| !self.is_preserved(&name));
+        // Length includes its own two bytes
+        let remaining = length.saturating_sub(2) as u64;
+        self.state = match is_stripped {
+            true => State::Skip { remaining },
+            false => {
+                output.extend_from_slice(&[0xff, marker]);
+                output.extend_from_slice(&self.header);
+                State::Copy { remaining }
+            },
+        };
+        self.header.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn encode(format: image::ImageFormat) -> Vec<u8> {
+        let image = image::RgbImage::from_fn(4, 3, |x, y| image::Rgb([x as u8 * 60, y as u8 * 80, 128]));
+        let mut content = Vec::new();
+        image.write_to(&mut Cursor::new(&mut content), format).unwrap();
+        content
+    }
+
+    fn insert(content: &[u8], offset: usize, inserted: &[u8]) -> Vec<u8> {
+        [&content[..offset], inserted, &content[offset..]].concat()
+    }
+
+    /// Feeds the content in slices of `slice_length` bytes and applies the patch returned by `finish`
+    fn strip(format: ImageFormat, content: &[u8], preserved: &[&str], slice_length: usize) -> Vec<u8> {
+        let preserved = preserved.iter().map(|v| v.to_string()).collect::<Vec<_>>().into();
+        let mut stripper = MetadataStripper::new(format, preserved).unwrap();
+        let mut output = Vec::new();
+        for slice in content.chunks(slice_length) {
+            stripper.update(slice, &mut output);
+        }
+        if let Some((offset, patch)) = stripper.finish() {
+            output[offset as usize..offset as usize + patch.len()].copy_from_slice(&patch);
+        }
+        output
+    }
+
+    fn check_round_trip(format: ImageFormat, original: &[u8], with_metadata: &[u8], preserved: &str) {
+        for slice_length in [1, 3, with_metadata.len()] {
+            let stripped = strip(format, with_metadata, &[], slice_length);
+            assert_eq!(stripped, original, "slice length {}", slice_length);
+            assert!(image::load_from_memory(&stripped).is_ok());
+            assert_eq!(strip(format, with_metadata, &[preserved], slice_length), with_metadata, "slice length {}", slice_length);
+        }
+    }
+
+    fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let crc = crc32fast::hash(&[chunk_type.as_slice(), data].concat());
+        [&(data.len() as u32).to_be_bytes(), chunk_type.as_slice(), data, &crc.to_be_bytes()].concat()
+    }
+
+    #[test]
+    fn png_text_chunks_are_stripped() {
+        let original = encode(image::ImageFormat::Png);
+        // Signature and IHDR
+        let with_metadata = insert(&original, 33, &png_chunk(b"tEXt", b"Author\0someone"));
+        check_round_trip(ImageFormat::Png, &original, &with_metadata, "tEXt");
+    }
+
+    #[test]
+    fn jpeg_app1_and_comment_segments_are_stripped() {
+        let original = encode(image::ImageFormat::Jpeg);
+        let exif = [&[0xff, 0xe1, 0x00, 0x0a][..], b"Exif\0\0ab"].concat();
+        let comment = [&[0xff, JPEG_COM, 0x00, 0x07][..], b"hello"].concat();
+        let with_metadata = insert(&insert(&original, 2, &comment), 2, &exif);
+        check_round_trip(ImageFormat::Jpeg, &original, &insert(&original, 2, &exif), "APP1");
+        check_round_trip(ImageFormat::Jpeg, &original, &insert(&original, 2, &comment), "COM");
+        assert_eq!(strip(ImageFormat::Jpeg, &with_metadata, &["APP1"], 5), insert(&original, 2, &exif));
+    }
+
+    fn webp_extended(flags: u8, chunks: &[u8]) -> Vec<u8> {
+        let vp8x = [&b"VP8X"[..], &10u32.to_le_bytes(), &[flags, 0, 0, 0, 3, 0, 0, 2, 0, 0]].concat();
+        let size = (4 + vp8x.len() + chunks.len()) as u32;
+        [&b"RIFF"[..], &size.to_le_bytes(), b"WEBP", &vp8x, chunks].concat()
+    }
+
+    #[test]
+    fn webp_exif_and_xmp_chunks_are_stripped() {
+        let encoded = encode(image::ImageFormat::WebP);
+        // The image chunk of the simple format follows the RIFF header
+        let image_chunk = &encoded[WEBP_HEADER_LENGTH..];
+        let exif = [&b"EXIF"[..], &4u32.to_le_bytes(), b"II*\0"].concat();
+        // Odd sized chunks are padded
+        let xmp = [&b"XMP "[..], &3u32.to_le_bytes(), b"<x>\0"].concat();
+        let original = webp_extended(0, image_chunk);
+        let with_metadata = webp_extended(WEBP_EXIF_FLAG | WEBP_XMP_FLAG, &[image_chunk, &exif, &xmp].concat());
+        for slice_length in [1, 7, with_metadata.len()] {
+            assert_eq!(strip(ImageFormat::WebP, &with_metadata, &[], slice_length), original, "slice length {}", slice_length);
+            assert_eq!(strip(ImageFormat::WebP, &with_metadata, &["EXIF", "XMP"], slice_length), with_metadata);
+        }
+        assert_eq!(
+            strip(ImageFormat::WebP, &with_metadata, &["XMP"], 4),
+            webp_extended(WEBP_XMP_FLAG, &[image_chunk, &xmp].concat()),
+        );
+        assert!(image::load_from_memory(&original).is_ok());
+    }
+
+    #[test]
+    fn gif_comment_and_xmp_extensions_are_stripped() {
+        let original = encode(image::ImageFormat::Gif);
+        let comment = [&[GIF_EXTENSION, GIF_COMMENT_LABEL, 5][..], b"hello", &[0]].concat();
+        let xmp = [&[GIF_EXTENSION, GIF_APPLICATION_LABEL, 11][..], GIF_XMP_APPLICATION, &[3], b"<x>", &[0]].concat();
+        let netscape = [&[GIF_EXTENSION, GIF_APPLICATION_LABEL, 11][..], b"NETSCAPE2.0", &[3, 1, 0, 0, 0]].concat();
+        // Extensions are placed before the trailer so the image data has to be parsed to reach them
+        let trailer = original.len() - 1;
+        check_round_trip(ImageFormat::Gif, &original, &insert(&original, trailer, &comment), "COM");
+        check_round_trip(ImageFormat::Gif, &original, &insert(&original, trailer, &xmp), "XMP");
+        let with_netscape = insert(&original, trailer, &netscape);
+        assert_eq!(strip(ImageFormat::Gif, &insert(&with_netscape, trailer, &xmp), &[], 2), with_netscape);
+    }
+}
@@ -1,3 +1,17 @@
+pub(crate) mod avatar_fetcher;
+pub(crate) mod avatar_job_queue;
 pub(crate) mod blog_post_service;
+pub(crate) mod byte_range;
+pub(crate) mod content_encoding;
+#[cfg(feature = "embedded-static-files")]
+pub(crate) mod embedded_static_files;
 pub(crate) mod file_handler_service;
+pub(crate) mod identicon;
+pub(crate) mod image_dimensions;
+pub(crate) mod image_format;
+pub(crate) mod image_garbage_collector;
+pub(crate) mod image_variants;
+pub(crate) mod metadata_stripper;
+pub(crate) mod png_validator;
 pub(crate) mod static_files_service;
+pub(crate) mod storage;
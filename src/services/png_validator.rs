@@ -0,0 +1,200 @@
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+const IHDR: [u8; 4] = *b"IHDR";
+const IDAT: [u8; 4] = *b"IDAT";
+const IEND: [u8; 4] = *b"IEND";
+const IHDR_LENGTH: usize = 13;
+// PNG specification limits chunk lengths and image dimensions to 2^31 - 1
+const MAX_PNG_VALUE: u32 = 0x7fff_ffff;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum PngValidationError {
+    #[error("Invalid PNG signature")]
+    InvalidSignature,
+    #[error("First chunk is not IHDR")]
+    FirstChunkIsNotIhdr,
+    #[error("Invalid IHDR chunk")]
+    InvalidIhdr,
+    #[error("Chunk has invalid length")]
+    InvalidChunkLength,
+    #[error("Chunk has invalid type")]
+    InvalidChunkType,
+    #[error("CRC of {0} chunk does not match")]
+    CrcMismatch(String),
+    #[error("Image does not contain any IDAT chunk")]
+    MissingImageData,
+    #[error("Image contains data after IEND chunk")]
+    DataAfterIend,
+    #[error("Image is truncated")]
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PngDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug)]
+enum State {
+    Signature,
+    ChunkHeader,
+    ChunkData { remaining: u32 },
+    ChunkCrc,
+    End,
+}
+
+/// Incrementally validates the chunk stream of a PNG file, the file can be fed in slices of any size
+#[derive(Debug)]
+pub(crate) struct PngValidator {
+    state: State,
+    // Bytes of the currently parsed fixed size field (signature, chunk header or CRC)
+    field: Vec<u8>,
+    chunk_type: [u8; 4],
+    crc: crc32fast::Hasher,
+    ihdr: Vec<u8>,
+    dimensions: Option<PngDimensions>,
+    has_image_data: bool,
+}
+
+impl PngValidator {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self {
+            state: State::Signature,
+            field: Vec::with_capacity(PNG_SIGNATURE.len()),
+            chunk_type: [0; 4],
+            crc: crc32fast::Hasher::new(),
+            ihdr: Vec::with_capacity(IHDR_LENGTH),
+            dimensions: None,
+            has_image_data: false,
+        }
+    }
+
+    /// Moves up to `length` bytes from the beginning of `data` to the currently parsed field,
+    /// returns `true` once the field is complete
+    #[inline]
+    fn fill_field(&mut self, data: &mut &[u8], length: usize) -> bool {
+        let taken = (length - self.field.len()).min(data.len());
+        self.field.extend_from_slice(&data[..taken]);
+        *data = &data[taken..];
+        self.field.len() == length
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) -> Result<(), PngValidationError> {
+        while !data.is_empty() {
+            match self.state {
+                State::Signature => {
+                    if self.fill_field(&mut data, PNG_SIGNATURE.len()) {
+                        if self.field != PNG_SIGNATURE {
+                            return Err(PngValidationError::InvalidSignature);
+                        }
+                        self.field.clear();
+                        self.state = State::ChunkHeader;
+                    }
+                },
+                State::ChunkHeader => {
+                    if self.fill_field(&mut data, 8) {
+                        self.start_chunk()?;
+                    }
+                },
+                State::ChunkData { remaining } => {
+                    let taken = (remaining as usize).min(data.len());
+                    self.crc.update(&data[..taken]);
+                    if self.chunk_type == IHDR {
+                        self.ihdr.extend_from_slice(&data[..taken]);
+                    }
+                    data = &data[taken..];
+                    self.state = match remaining as usize - taken {
+                        0 => State::ChunkCrc,
+                        remaining => State::ChunkData { remaining: remaining as u32 },
+                    };
+                },
+                State::ChunkCrc => {
+                    if self.fill_field(&mut data, 4) {
+                        self.end_chunk()?;
+                    }
+                },
+                State::End => return Err(PngValidationError::DataAfterIend),
+            }
+        }
+        Ok(())
+    }
+
+    fn start_chunk(&mut self) -> Result<(), PngValidationError> {
+        let length = u32::from_be_bytes([self.field[0], self.field[1], self.field[2], self.field[3]]);
+        self.chunk_type = [self.field[4], self.field[5], self.field[6], self.field[7]];
+        self.field.clear();
+        if length > MAX_PNG_VALUE {
+            return Err(PngValidationError::InvalidChunkLength);
+        }
+        if !self.chunk_type.iter().all(u8::is_ascii_alphabetic) {
+            return Err(PngValidationError::InvalidChunkType);
+        }
+        match (self.dimensions.is_some(), self.chunk_type) {
+            (false, IHDR) if length as usize != IHDR_LENGTH => return Err(PngValidationError::InvalidIhdr),
+            (false, IHDR) => (),
+            (false, _) => return Err(PngValidationError::FirstChunkIsNotIhdr),
+            (true, IHDR) => return Err(PngValidationError::InvalidIhdr),
+            (true, IEND) if length != 0 => return Err(PngValidationError::InvalidChunkLength),
+            (true, IDAT) => self.has_image_data = true,
+            (true, _) => (),
+        }
+        self.crc = crc32fast::Hasher::new();
+        self.crc.update(&self.chunk_type);
+        self.state = match length {
+            0 => State::ChunkCrc,
+            length => State::ChunkData { remaining: length },
+        };
+        Ok(())
+    }
+
+    fn end_chunk(&mut self) -> Result<(), PngValidationError> {
+        let crc = u32::from_be_bytes([self.field[0], self.field[1], self.field[2], self.field[3]]);
+        self.field.clear();
+        if std::mem::replace(&mut self.crc, crc32fast::Hasher::new()).finalize() != crc {
+            return Err(PngValidationError::CrcMismatch(String::from_utf8_lossy(&self.chunk_type).into_owned()));
+        }
+        self.state = match self.chunk_type {
+            IHDR => {
+                self.dimensions = Some(Self::parse_ihdr(&self.ihdr)?);
+                State::ChunkHeader
+            },
+            IEND if !self.has_image_data => return Err(PngValidationError::MissingImageData),
+            IEND => State::End,
+            _ => State::ChunkHeader,
+        };
+        Ok(())
+    }
+
+    fn parse_ihdr(ihdr: &[u8]) -> Result<PngDimensions, PngValidationError> {
+        let width = u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]);
+        let height = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]);
+        let (bit_depth, color_type) = (ihdr[8], ihdr[9]);
+        let (compression_method, filter_method, interlace_method) = (ihdr[10], ihdr[11], ihdr[12]);
+        let is_valid_bit_depth = match color_type {
+            0 => [1, 2, 4, 8, 16].contains(&bit_depth),
+            3 => [1, 2, 4, 8].contains(&bit_depth),
+            2 | 4 | 6 => [8, 16].contains(&bit_depth),
+            _ => false,
+        };
+        if width == 0 || height == 0 || width > MAX_PNG_VALUE || height > MAX_PNG_VALUE
+            || !is_valid_bit_depth || compression_method != 0 || filter_method != 0 || interlace_method > 1 {
+            return Err(PngValidationError::InvalidIhdr);
+        }
+        Ok(PngDimensions { width, height })
+    }
+
+    /// Available as soon as the IHDR chunk has been validated
+    #[inline]
+    pub(crate) fn get_dimensions(&self) -> Option<PngDimensions> {
+        self.dimensions
+    }
+
+    /// Has to be called once the whole file has been fed to the validator
+    pub(crate) fn finish(self) -> Result<PngDimensions, PngValidationError> {
+        match (self.state, self.dimensions) {
+            (State::End, Some(dimensions)) => Ok(dimensions),
+            _ => Err(PngValidationError::Truncated),
+        }
+    }
+}
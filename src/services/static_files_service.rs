@@ -1,26 +1,174 @@
 use std::path::PathBuf;
 
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder};
+use axum::body::Bytes;
+use futures::{stream, TryStreamExt};
+use tokio_util::io::{ReaderStream, StreamReader};
 
-use super::file_handler_service::{get_file_from_directory, GetFileFromDirectoryError};
+use super::{byte_range::ByteRange, content_encoding::ContentEncoding, storage::ByteStream, file_handler_service::{get_file_from_directory, get_file_metadata_from_directory, GetFileFromDirectoryError}, image_format::ImageFormat};
 
+/// Amount of bytes read from the beginning of a file to recognise its type when the extension is unknown
+const SNIFFED_LENGTH: u64 = 512;
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Recognises the type by magic bytes, text which is not HTML is assumed to be plain
+fn sniff_mime_type(header: &[u8]) -> &'static str {
+    if let Some(format) = ImageFormat::detect(header) {
+        return format.get_mime_type();
+    }
+    match header {
+        _ if header.starts_with(b"%PDF-") => "application/pdf",
+        _ if header.starts_with(b"wOFF") => "font/woff",
+        _ if header.starts_with(b"wOF2") => "font/woff2",
+        _ if header.starts_with(&[0x00, 0x00, 0x01, 0x00]) => "image/x-icon",
+        _ if header.starts_with(&[0x1f, 0x8b]) => "application/gzip",
+        _ if header.starts_with(b"PK\x03\x04") => "application/zip",
+        _ => {
+            // The header may end in the middle of a multibyte character
+            let text = match std::str::from_utf8(header) {
+                Ok(text) => text,
+                Err(err) if err.error_len().is_none() => std::str::from_utf8(&header[..err.valid_up_to()]).unwrap_or_default(),
+                Err(_) => return DEFAULT_MIME_TYPE,
+            };
+            let text = text.trim_start().to_ascii_lowercase();
+            match text.starts_with("<!doctype html") || text.starts_with("<html") {
+                true => "text/html; charset=utf-8",
+                false => "text/plain; charset=utf-8",
+            }
+        },
+    }
+}
+
+#[inline]
+fn is_text_mime_type(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || matches!(essence, "application/javascript" | "application/json" | "application/xml" | "image/svg+xml")
+}
 
 pub(crate) struct StaticFilesService {
-    static_files_directory: PathBuf,
+    // Without embedded static files the directory is the only source, otherwise files in it take precedence
+    static_files_directory: Option<PathBuf>,
+    // Text files at least this large are compressed on the fly when they have no precompressed sibling
+    compression_min_size: u64,
 }
 
 impl StaticFilesService {
-    pub(crate) fn new(static_files_directory: &str) -> Option<Self> {
-        let static_files_directory = PathBuf::from(static_files_directory).canonicalize().ok()?;
-        match static_files_directory.is_dir() {
-            true => Some(Self { static_files_directory }),
-            false => None,
+    pub(crate) fn new(static_files_directory: Option<&str>, compression_min_size: u64) -> Option<Self> {
+        let static_files_directory = match static_files_directory {
+            Some(static_files_directory) => {
+                let static_files_directory = PathBuf::from(static_files_directory).canonicalize().ok()?;
+                match static_files_directory.is_dir() {
+                    true => Some(static_files_directory),
+                    false => return None,
+                }
+            },
+            None if cfg!(feature = "embedded-static-files") => None,
+            None => return None,
+        };
+        Some(Self { static_files_directory, compression_min_size })
+    }
+
+    #[inline]
+    #[cfg(feature = "embedded-static-files")]
+    fn get_embedded_static_file(file_name: &str) -> Result<&'static [u8], GetFileFromDirectoryError> {
+        super::embedded_static_files::get_embedded_static_file(file_name)
+            .ok_or(GetFileFromDirectoryError::FileNotFound)
+    }
+
+    #[inline]
+    #[cfg(not(feature = "embedded-static-files"))]
+    fn get_embedded_static_file(_file_name: &str) -> Result<&'static [u8], GetFileFromDirectoryError> {
+        Err(GetFileFromDirectoryError::FileNotFound)
+    }
+
+    pub(crate) async fn get_static_file(
+        &self, file_name: &str, range: Option<ByteRange>
+    ) -> Result<ByteStream, GetFileFromDirectoryError> {
+        if let Some(static_files_directory) = &self.static_files_directory {
+            match get_file_from_directory(static_files_directory.clone(), file_name, range).await {
+                Err(GetFileFromDirectoryError::FileNotFound) => (),
+                result => return Ok(Box::pin(result?)),
+            }
         }
+        let content = Self::get_embedded_static_file(file_name)?;
+        // The range is clamped as sniffing the type reads a fixed amount of bytes even from smaller files
+        let content = match range {
+            Some(range) => &content[(range.start as usize).min(content.len())..(range.end as usize + 1).min(content.len())],
+            None => content,
+        };
+        Ok(Box::pin(stream::once(async move { Ok(Bytes::from_static(content)) })))
+    }
+
+    /// Returns `None` if the file is not in the static files directory
+    async fn get_file_size_on_disk(&self, file_name: &str) -> Result<Option<u64>, GetFileFromDirectoryError> {
+        let Some(static_files_directory) = &self.static_files_directory else {
+            return Ok(None);
+        };
+        match get_file_metadata_from_directory(static_files_directory.clone(), file_name).await {
+            Err(GetFileFromDirectoryError::FileNotFound) => Ok(None),
+            result => Ok(Some(result?.len())),
+        }
+    }
+
+    pub(crate) async fn get_static_file_size(&self, file_name: &str) -> Result<u64, GetFileFromDirectoryError> {
+        if let Some(size) = self.get_file_size_on_disk(file_name).await? {
+            return Ok(size);
+        }
+        Ok(Self::get_embedded_static_file(file_name)?.len() as u64)
+    }
+
+    /// Guesses the type from the extension, falls back to the content of the file
+    pub(crate) async fn get_static_file_mime_type(&self, file_name: &str) -> Result<String, GetFileFromDirectoryError> {
+        if let Some(mime_type) = mime_guess::from_path(file_name).first() {
+            return Ok(match mime_type.type_() == mime_guess::mime::TEXT || mime_type == mime_guess::mime::APPLICATION_JAVASCRIPT {
+                true => format!("{}; charset=utf-8", mime_type.essence_str()),
+                false => mime_type.essence_str().to_string(),
+            });
+        }
+        let header = self.get_static_file(file_name, Some(ByteRange { start: 0, end: SNIFFED_LENGTH - 1 })).await?
+            .try_fold(Vec::new(), |mut header, chunk| async move {
+                header.extend_from_slice(&chunk);
+                Ok(header)
+            }).await?;
+        Ok(sniff_mime_type(&header).to_string())
+    }
+
+    /// Returns the first of the encodings for which the file has a precompressed sibling, with the name and size of the sibling.
+    /// Only siblings from the same source as the file are used, an embedded sibling of an overridden file would be stale
+    pub(crate) async fn get_precompressed_file(
+        &self, file_name: &str, encodings: &[ContentEncoding]
+    ) -> Result<Option<(ContentEncoding, String, u64)>, GetFileFromDirectoryError> {
+        let is_on_disk = self.get_file_size_on_disk(file_name).await?.is_some();
+        for encoding in encodings {
+            let compressed_file_name = format!("{}.{}", file_name, encoding.get_extension());
+            let size = match (is_on_disk, self.get_file_size_on_disk(&compressed_file_name).await?) {
+                (true, size) => size,
+                // A sibling on disk would be served instead of the embedded one
+                (false, Some(_)) => None,
+                (false, None) => Self::get_embedded_static_file(&compressed_file_name).ok().map(|v| v.len() as u64),
+            };
+            if let Some(size) = size {
+                return Ok(Some((*encoding, compressed_file_name, size)));
+            }
+        }
+        Ok(None)
     }
 
     #[inline]
-    pub(crate) async fn get_static_file(&self, file_name: &str) -> Result<ReaderStream<File>, GetFileFromDirectoryError> {
-        get_file_from_directory(self.static_files_directory.clone(), file_name).await
+    pub(crate) fn is_compressible(&self, mime_type: &str, size: u64) -> bool {
+        size >= self.compression_min_size && is_text_mime_type(mime_type)
+    }
+
+    /// Streams the whole file compressed on the fly, the size of the result is not known upfront
+    pub(crate) async fn get_compressed_static_file(
+        &self, file_name: &str, encoding: ContentEncoding
+    ) -> Result<ByteStream, GetFileFromDirectoryError> {
+        let file = StreamReader::new(self.get_static_file(file_name, None).await?);
+        Ok(match encoding {
+            ContentEncoding::Brotli => Box::pin(ReaderStream::new(BrotliEncoder::new(file))),
+            ContentEncoding::Gzip => Box::pin(ReaderStream::new(GzipEncoder::new(file))),
+        })
     }
 }
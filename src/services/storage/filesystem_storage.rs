@@ -0,0 +1,122 @@
+use std::{path::{Path, PathBuf}, time::SystemTime};
+use futures::TryStreamExt;
+use tokio::io::AsyncWriteExt;
+use crate::services::{byte_range::ByteRange, file_handler_service::{get_file_from_directory, GetFileFromDirectoryError}};
+use super::{ByteStream, StorageBackend, StorageError, StoredObject};
+
+/// Stores objects as files directly in a local directory, subdirectories are ignored
+#[derive(Debug)]
+pub(crate) struct FilesystemStorage {
+    folder_path: PathBuf,
+}
+
+impl FilesystemStorage {
+    pub(crate) fn new(folder_path: &str) -> Option<Self> {
+        let folder_path = PathBuf::from(folder_path).canonicalize().ok()?;
+        match folder_path.is_dir() {
+            true => Some(Self { folder_path }),
+            false => None,
+        }
+    }
+
+    #[inline]
+    fn get_stored_object(key: String, metadata: &std::fs::Metadata) -> StoredObject {
+        StoredObject {
+            key,
+            size: metadata.len(),
+            last_modified: metadata.modified().unwrap_or(SystemTime::now()),
+        }
+    }
+
+    async fn get_range_or_file(&self, key: &str, range: Option<ByteRange>) -> Result<ByteStream, StorageError> {
+        self.get_path(key)?;
+        let file = get_file_from_directory(self.folder_path.clone(), key, range).await
+            .map_err(|err| match err {
+                GetFileFromDirectoryError::FileNotFound => StorageError::NotFound,
+                GetFileFromDirectoryError::PathNotInAllowedDirectory => StorageError::InvalidKey,
+                GetFileFromDirectoryError::TokioIoError(err) if err.kind() == tokio::io::ErrorKind::NotFound =>
+                    StorageError::NotFound,
+                GetFileFromDirectoryError::TokioIoError(err) => err.into(),
+            })?;
+        Ok(Box::pin(file))
+    }
+
+    // Keys are used as filenames so they cannot reach outside of the directory
+    #[inline]
+    fn get_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        match key.is_empty() || key.starts_with('.') || key.contains(['/', '\\']) {
+            true => Err(StorageError::InvalidKey),
+            false => Ok(self.folder_path.join(key)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for FilesystemStorage {
+    async fn put(&self, key: &str, mut content: ByteStream) -> Result<(), StorageError> {
+        let path = self.get_path(key)?;
+        // Written under a hidden name first so the rename makes the file appear atomically
+        let temporary_path = self.folder_path.join(format!(".{}.partial", key));
+        let result = async {
+            let mut file = tokio::fs::File::create(&temporary_path).await?;
+            while let Some(chunk) = content.try_next().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            tokio::fs::rename(&temporary_path, &path).await
+        }.await;
+        if result.is_err() {
+            tokio::fs::remove_file(&temporary_path).await.ok();
+        }
+        Ok(result?)
+    }
+
+    async fn get(&self, key: &str) -> Result<ByteStream, StorageError> {
+        self.get_range_or_file(key, None).await
+    }
+
+    async fn get_range(&self, key: &str, range: ByteRange) -> Result<ByteStream, StorageError> {
+        self.get_range_or_file(key, Some(range)).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.get_path(key)?).await {
+            Err(err) if err.kind() == tokio::io::ErrorKind::NotFound => Err(StorageError::NotFound),
+            result => Ok(result?),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        Ok(tokio::fs::try_exists(self.get_path(key)?).await?)
+    }
+
+    async fn head(&self, key: &str) -> Result<StoredObject, StorageError> {
+        match tokio::fs::metadata(self.get_path(key)?).await {
+            Ok(metadata) if metadata.is_file() => Ok(Self::get_stored_object(key.to_string(), &metadata)),
+            Ok(_) => Err(StorageError::NotFound),
+            Err(err) if err.kind() == tokio::io::ErrorKind::NotFound => Err(StorageError::NotFound),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError> {
+        let mut objects = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.folder_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let Ok(key) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Hidden files are not valid keys, e.g. partially written files of `put`
+            if metadata.is_file() && !key.starts_with('.') {
+                objects.push(Self::get_stored_object(key, &metadata));
+            }
+        }
+        Ok(objects)
+    }
+
+    // Staged files are expected to be on the same filesystem so they can be simply renamed
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        Ok(tokio::fs::rename(path, self.get_path(key)?).await?)
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fmt::Debug, path::Path, time::SystemTime};
+use axum::body::Bytes;
+use futures::stream::BoxStream;
+use tokio_util::io::ReaderStream;
+use super::byte_range::ByteRange;
+
+pub(crate) mod filesystem_storage;
+pub(crate) mod object_storage;
+
+pub(crate) type ByteStream = BoxStream<'static, Result<Bytes, tokio::io::Error>>;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum StorageError {
+    #[error("Object not found")]
+    NotFound,
+    #[error("Invalid object key")]
+    InvalidKey,
+    #[error("Tokio IO error: {0}")]
+    TokioIoError(#[from] tokio::io::Error),
+    #[error("Object store error: {0}")]
+    ObjectStoreError(object_store::Error),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: SystemTime,
+}
+
+/// Storage of uploaded images and their variants, objects are identified by flat keys (the image filenames)
+#[async_trait::async_trait]
+pub(crate) trait StorageBackend: Debug + Send + Sync {
+    /// Stores the object, readers never observe a partially written object
+    async fn put(&self, key: &str, content: ByteStream) -> Result<(), StorageError>;
+
+    async fn get(&self, key: &str) -> Result<ByteStream, StorageError>;
+
+    /// The range is expected to lie within the object
+    async fn get_range(&self, key: &str, range: ByteRange) -> Result<ByteStream, StorageError>;
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    async fn exists(&self, key: &str) -> Result<bool, StorageError>;
+
+    /// Returns `StorageError::NotFound` if there is no such object
+    async fn head(&self, key: &str) -> Result<StoredObject, StorageError>;
+
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError>;
+
+    /// Moves a local file into the storage, the file is removed once it has been stored
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        let file = tokio::fs::File::open(path).await?;
+        self.put(key, Box::pin(ReaderStream::new(file))).await?;
+        tokio::fs::remove_file(path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use super::{filesystem_storage::FilesystemStorage, object_storage::ObjectStorage, *};
+
+    fn to_stream(chunks: &[&'static str]) -> ByteStream {
+        Box::pin(futures::stream::iter(chunks.iter().map(|v| Ok(Bytes::from_static(v.as_bytes()))).collect::<Vec<_>>()))
+    }
+
+    async fn read(stream: ByteStream) -> String {
+        let content = stream.try_fold(Vec::new(), |mut content, chunk| async move {
+            content.extend_from_slice(&chunk);
+            Ok(content)
+        }).await.unwrap();
+        String::from_utf8(content).unwrap()
+    }
+
+    /// Runs every operation of the backend, the backend has to be empty
+    async fn check_backend(storage: &dyn StorageBackend) {
+        storage.put("a", to_stream(&["hello ", "world"])).await.unwrap();
+        assert_eq!(read(storage.get("a").await.unwrap()).await, "hello world");
+        assert_eq!(read(storage.get_range("a", ByteRange { start: 6, end: 10 }).await.unwrap()).await, "world");
+        assert_eq!(read(storage.get_range("a", ByteRange { start: 0, end: 0 }).await.unwrap()).await, "h");
+
+        let object = storage.head("a").await.unwrap();
+        assert_eq!(object.key, "a");
+        assert_eq!(object.size, 11);
+        assert!(storage.exists("a").await.unwrap());
+        assert!(!storage.exists("b").await.unwrap());
+        assert!(matches!(storage.head("b").await, Err(StorageError::NotFound)));
+        assert!(matches!(storage.get("b").await, Err(StorageError::NotFound)));
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("upload");
+        tokio::fs::write(&path, "moved").await.unwrap();
+        storage.put_file("b", &path).await.unwrap();
+        assert!(!tokio::fs::try_exists(&path).await.unwrap());
+        assert_eq!(read(storage.get("b").await.unwrap()).await, "moved");
+
+        // Overwriting replaces the whole object
+        storage.put("b", to_stream(&["new"])).await.unwrap();
+        assert_eq!(read(storage.get("b").await.unwrap()).await, "new");
+
+        let mut keys = storage.list().await.unwrap().into_iter().map(|v| v.key).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+
+        storage.delete("a").await.unwrap();
+        assert!(!storage.exists("a").await.unwrap());
+        assert_eq!(storage.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn object_storage_over_in_memory_store() {
+        check_backend(&ObjectStorage::new(object_store::memory::InMemory::new())).await;
+    }
+
+    #[tokio::test]
+    async fn filesystem_storage() {
+        let directory = tempfile::tempdir().unwrap();
+        let storage = FilesystemStorage::new(directory.path().to_str().unwrap()).unwrap();
+        check_backend(&storage).await;
+        assert!(matches!(storage.delete("a").await, Err(StorageError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn filesystem_storage_rejects_keys_outside_of_the_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let storage = FilesystemStorage::new(directory.path().to_str().unwrap()).unwrap();
+        for key in ["", "../a", "a/b", "a\\b", ".hidden"] {
+            assert!(matches!(storage.put(key, to_stream(&["x"])).await, Err(StorageError::InvalidKey)), "{}", key);
+            assert!(matches!(storage.get(key).await, Err(StorageError::InvalidKey)), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn filesystem_storage_does_not_list_partial_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let storage = FilesystemStorage::new(directory.path().to_str().unwrap()).unwrap();
+        tokio::fs::write(directory.path().join(".a.partial"), "partial").await.unwrap();
+        tokio::fs::create_dir(directory.path().join("subdirectory")).await.unwrap();
+        storage.put("b", to_stream(&["b"])).await.unwrap();
+        let keys = storage.list().await.unwrap().into_iter().map(|v| v.key).collect::<Vec<_>>();
+        assert_eq!(keys, ["b"]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use futures::TryStreamExt;
+use object_store::{path::Path, GetOptions, GetRange, ObjectMeta, ObjectStore, WriteMultipart};
+use crate::services::byte_range::ByteRange;
+use super::{ByteStream, StorageBackend, StorageError, StoredObject};
+
+// Amount of parts uploaded concurrently before writing waits for them to finish
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+impl From<object_store::Error> for StorageError {
+    fn from(err: object_store::Error) -> Self {
+        match err {
+            object_store::Error::NotFound { .. } => StorageError::NotFound,
+            object_store::Error::InvalidPath { .. } => StorageError::InvalidKey,
+            err => StorageError::ObjectStoreError(err),
+        }
+    }
+}
+
+/// Stores objects in any `object_store` implementation, e.g. S3 compatible services or `InMemory`
+#[derive(Debug)]
+pub(crate) struct ObjectStorage {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl ObjectStorage {
+    #[inline]
+    pub(crate) fn new(store: impl ObjectStore) -> Self {
+        Self { store: Arc::new(store) }
+    }
+
+    #[inline]
+    fn get_stored_object(meta: ObjectMeta) -> StoredObject {
+        StoredObject {
+            key: meta.location.to_string(),
+            size: meta.size as u64,
+            last_modified: meta.last_modified.into(),
+        }
+    }
+
+    #[inline]
+    fn get_path(key: &str) -> Result<Path, StorageError> {
+        Path::parse(key).map_err(|_| StorageError::InvalidKey)
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for ObjectStorage {
+    // Multipart upload only becomes visible once it is completed
+    async fn put(&self, key: &str, mut content: ByteStream) -> Result<(), StorageError> {
+        let upload = self.store.put_multipart(&Self::get_path(key)?).await?;
+        let mut writer = WriteMultipart::new(upload);
+        let result = async {
+            while let Some(chunk) = content.try_next().await? {
+                writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+                writer.write(&chunk);
+            }
+            Ok::<_, StorageError>(())
+        }.await;
+        match result {
+            Ok(()) => {
+                writer.finish().await?;
+                Ok(())
+            },
+            Err(err) => {
+                writer.abort().await.ok();
+                Err(err)
+            },
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<ByteStream, StorageError> {
+        let result = self.store.get(&Self::get_path(key)?).await?;
+        Ok(Box::pin(result.into_stream().map_err(tokio::io::Error::other)))
+    }
+
+    async fn get_range(&self, key: &str, range: ByteRange) -> Result<ByteStream, StorageError> {
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(range.start as usize..range.end as usize + 1)),
+            ..Default::default()
+        };
+        let result = self.store.get_opts(&Self::get_path(key)?, options).await?;
+        Ok(Box::pin(result.into_stream().map_err(tokio::io::Error::other)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        Ok(self.store.delete(&Self::get_path(key)?).await?)
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        match self.store.head(&Self::get_path(key)?).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<StoredObject, StorageError> {
+        Ok(Self::get_stored_object(self.store.head(&Self::get_path(key)?).await?))
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError> {
+        Ok(self.store.list(None)
+            .map_ok(Self::get_stored_object)
+            .try_collect().await?)
+    }
+}
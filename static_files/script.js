@@ -21,7 +21,7 @@ function display_posts(posts) {
                 <header>
                     ${user_avatar}
                     <div>
-                        <b>${post.user_name}</b> <i>date: ${date_locale} ${time_locale}</i>
+                        <b>${post.user_name}</b> <i><a href="/home/post/${post.id}">date: ${date_locale} ${time_locale}</a> replies: ${post.reply_count}</i>
                     </div>
                 </header>
                 <p>